@@ -0,0 +1,77 @@
+//! Long running while loops on `Machine` and `ArenaMachine`.
+//!
+//! Both loops count to `n` with a loop body containing a large branch that is never taken.
+//! `Machine` copies that branch on every unfolding of the loop, `ArenaMachine` shares it, so
+//! only its time grows linearly with `n` independent of the size of the body.
+
+#![feature(box_syntax,test)]
+
+extern crate small_step_simple;
+extern crate test;
+
+use std::collections::hash_map::HashMap;
+
+use small_step_simple::{Element, Machine};
+use small_step_simple::arena::ArenaMachine;
+use test::Bencher;
+
+/// while (i < n) [ if (false) [ unused = 0; ...; unused = size - 1 ] else [ i = i + 1 ] ]
+fn counting_loop(n: i64, size: i64) -> Box<Element> {
+    let mut unused = box Element::DoNothing;
+    for k in 0..size {
+        unused = box Element::Sequence(box Element::Assign("unused".to_string(), box Element::Number(k)), unused);
+    }
+
+    box Element::While(
+        box Element::LessThan(box Element::Variable("i".to_string()), box Element::Number(n)),
+        box Element::IfElse(
+            box Element::Boolean(false),
+            unused,
+            box Element::Assign(
+                "i".to_string(),
+                box Element::Add(box Element::Variable("i".to_string()), box Element::Number(1)))),
+        None)
+}
+
+fn env() -> HashMap<String, Box<Element>> {
+    let mut env = HashMap::new();
+    env.insert("i".to_string(), box Element::Number(0));
+    env
+}
+
+fn run_machine(n: i64, size: i64) {
+    let mut m = Machine::new(counting_loop(n, size), env());
+    while m.expression().is_reducible() {
+        m.step();
+    }
+}
+
+fn run_arena(n: i64, size: i64) {
+    let mut m = ArenaMachine::new(counting_loop(n, size), env()).unwrap();
+    m.run();
+}
+
+#[bench]
+fn machine_100_iterations(b: &mut Bencher) {
+    b.iter(|| run_machine(100, 200));
+}
+
+#[bench]
+fn machine_1000_iterations(b: &mut Bencher) {
+    b.iter(|| run_machine(1000, 200));
+}
+
+#[bench]
+fn arena_100_iterations(b: &mut Bencher) {
+    b.iter(|| run_arena(100, 200));
+}
+
+#[bench]
+fn arena_1000_iterations(b: &mut Bencher) {
+    b.iter(|| run_arena(1000, 200));
+}
+
+#[bench]
+fn arena_10000_iterations(b: &mut Bencher) {
+    b.iter(|| run_arena(10000, 200));
+}
@@ -0,0 +1,813 @@
+//! Abstract interpretation of SIMPLE programs.
+//!
+//! Instead of running a program on numbers, `abstract_eval` runs it on abstract values, e.g.
+//! just the signs of numbers, covering every possible run at once. Loops are run until the
+//! abstract environment at their head doesn't change any more, widening it so that this always
+//! happens, and then narrowed again. The result describes every environment the program can end
+//! with when it completes normally:
+//!
+//! ```ignore
+//! let program = sequence!(
+//!     assign!("x", number!(1)),
+//!     while_!(less_than!(variable!("x"), number!(100)), assign!("x", multiply!(variable!("x"), number!(2))))
+//!     );
+//!
+//! let (_, env) = abstract_eval::<Sign>(&program, &HashMap::new()).unwrap();
+//! assert_eq!(Some(&Abstract::Number(Sign::Positive)), env.get("x"));
+//! ```
+//!
+//! `analyze` evaluates a program the same way and reports the additions and multiplications that
+//! may overflow and the conditions that always have the same value. It is most precise with
+//! `Interval`s.
+//!
+//! Runs that throw, fail an assertion or stop with a type mismatch are not covered. Elements
+//! other than those of the SIMPLE language of the book, `print`, `read` and `assert` are not
+//! looked into: every variable they assign may hold anything afterwards. The same goes for
+//! loops containing `break` or `continue`.
+
+use std::cmp;
+use std::collections::hash_map::HashMap;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Result;
+
+use Element;
+
+/// A lattice of abstract numbers, the domain of an abstract interpretation.
+pub trait Lattice: Clone + PartialEq + Debug {
+    /// Any number at all.
+    fn top() -> Self;
+    /// The abstraction of a single number.
+    fn number(n: i64) -> Self;
+    /// The least abstract number covering both.
+    fn join(&self, other: &Self) -> Self;
+    /// Like `join`, but only a finite number of widenings can be chained before the result
+    /// stays the same. The default is enough for lattices without infinite ascending chains.
+    fn widen(&self, next: &Self) -> Self {
+        self.join(next)
+    }
+    /// Improve a widened abstract number using the next approximation. The default doesn't
+    /// improve it at all.
+    fn narrow(&self, _next: &Self) -> Self {
+        self.clone()
+    }
+    fn add(&self, other: &Self) -> Self;
+    fn multiply(&self, other: &Self) -> Self;
+    /// Wether adding two of the numbers may overflow. The default assumes it may.
+    fn add_may_overflow(&self, _other: &Self) -> bool {
+        true
+    }
+    /// Wether multiplying two of the numbers may overflow. The default assumes it may.
+    fn multiply_may_overflow(&self, _other: &Self) -> bool {
+        true
+    }
+    /// Wether every number of `self` is less than every number of `other`, `None` if it
+    /// depends.
+    fn less_than(&self, other: &Self) -> Option<bool>;
+    /// Restrict both sides given wether `self < other` holds, `None` if it can't. The default
+    /// only rules out what `less_than` rules out.
+    fn assume_less_than(&self, other: &Self, holds: bool) -> Option<(Self, Self)> {
+        match self.less_than(other) {
+            Some(result) if result != holds => None,
+            _ => Some((self.clone(), other.clone()))
+        }
+    }
+}
+
+/// The abstract value of a variable or expression.
+#[derive(Clone,PartialEq,Debug)]
+pub enum Abstract<L> {
+    Number(L),
+    /// A boolean, `None` if it may be either.
+    Boolean(Option<bool>),
+    /// Anything, not necessarily a number or boolean.
+    Unknown
+}
+
+impl<L: Lattice> Abstract<L> {
+    /// The abstraction of a concrete value.
+    pub fn of(element: &Element) -> Abstract<L> {
+        match *element {
+            Element::Number(n) => Abstract::Number(L::number(n)),
+            Element::Boolean(b) => Abstract::Boolean(Some(b)),
+            _ => Abstract::Unknown
+        }
+    }
+
+    /// The numbers this value stands for in arithmetic, where booleans count as 1 and 0.
+    fn number(&self) -> L {
+        match *self {
+            Abstract::Number(ref n) => n.clone(),
+            Abstract::Boolean(Some(b)) => L::number(if b { 1 } else { 0 }),
+            Abstract::Boolean(None) => L::number(0).join(&L::number(1)),
+            // If the arithmetic completes at all, it was a number.
+            Abstract::Unknown => L::top()
+        }
+    }
+
+    fn combine<F>(&self, other: &Abstract<L>, f: F) -> Abstract<L> where F: Fn(&L, &L) -> L {
+        match (self, other) {
+            (&Abstract::Number(ref a), &Abstract::Number(ref b)) => Abstract::Number(f(a, b)),
+            (&Abstract::Boolean(a), &Abstract::Boolean(b)) => Abstract::Boolean(if a == b { a } else { None }),
+            _ => Abstract::Unknown
+        }
+    }
+
+    pub fn join(&self, other: &Abstract<L>) -> Abstract<L> {
+        self.combine(other, |a, b| a.join(b))
+    }
+
+    pub fn widen(&self, next: &Abstract<L>) -> Abstract<L> {
+        self.combine(next, |a, b| a.widen(b))
+    }
+
+    pub fn narrow(&self, next: &Abstract<L>) -> Abstract<L> {
+        match (self, next) {
+            (&Abstract::Number(ref a), &Abstract::Number(ref b)) => Abstract::Number(a.narrow(b)),
+            _ => self.clone()
+        }
+    }
+}
+
+/// The abstraction of a concrete environment.
+pub fn abstraction<L: Lattice>(environment: &HashMap<String, Box<Element>>) -> HashMap<String, Abstract<L>> {
+    environment.iter().map(|(name, val)| (name.clone(), Abstract::of(val))).collect()
+}
+
+/// The sign of a number.
+#[derive(Clone,Copy,PartialEq,Eq)]
+pub enum Sign {
+    Negative,
+    Zero,
+    Positive,
+    /// Any sign.
+    Unknown
+}
+
+impl Debug for Sign {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match *self {
+            Sign::Negative => write!(f, "-"),
+            Sign::Zero => write!(f, "0"),
+            Sign::Positive => write!(f, "+"),
+            Sign::Unknown => write!(f, "?")
+        }
+    }
+}
+
+impl Lattice for Sign {
+    fn top() -> Sign {
+        Sign::Unknown
+    }
+
+    fn number(n: i64) -> Sign {
+        if n < 0 { Sign::Negative } else if n == 0 { Sign::Zero } else { Sign::Positive }
+    }
+
+    fn join(&self, other: &Sign) -> Sign {
+        if self == other { *self } else { Sign::Unknown }
+    }
+
+    fn add(&self, other: &Sign) -> Sign {
+        match (*self, *other) {
+            (Sign::Zero, s) | (s, Sign::Zero) => s,
+            (a, b) if a == b => a,
+            _ => Sign::Unknown
+        }
+    }
+
+    fn multiply(&self, other: &Sign) -> Sign {
+        match (*self, *other) {
+            (Sign::Zero, _) | (_, Sign::Zero) => Sign::Zero,
+            (Sign::Unknown, _) | (_, Sign::Unknown) => Sign::Unknown,
+            (a, b) if a == b => Sign::Positive,
+            _ => Sign::Negative
+        }
+    }
+
+    fn add_may_overflow(&self, other: &Sign) -> bool {
+        match (*self, *other) {
+            (Sign::Zero, _) | (_, Sign::Zero) => false,
+            (Sign::Negative, Sign::Positive) | (Sign::Positive, Sign::Negative) => false,
+            _ => true
+        }
+    }
+
+    fn multiply_may_overflow(&self, other: &Sign) -> bool {
+        *self != Sign::Zero && *other != Sign::Zero
+    }
+
+    fn less_than(&self, other: &Sign) -> Option<bool> {
+        match (*self, *other) {
+            (Sign::Negative, Sign::Zero) | (Sign::Negative, Sign::Positive) |
+            (Sign::Zero, Sign::Positive) => Some(true),
+            (Sign::Zero, Sign::Zero) | (Sign::Zero, Sign::Negative) |
+            (Sign::Positive, Sign::Zero) | (Sign::Positive, Sign::Negative) => Some(false),
+            _ => None
+        }
+    }
+
+    fn assume_less_than(&self, other: &Sign, holds: bool) -> Option<(Sign, Sign)> {
+        match (self.less_than(other), *self, *other) {
+            (Some(result), _, _) if result != holds => None,
+            // Only `x < 0` and `0 < x` tell anything about an unknown sign.
+            (None, Sign::Unknown, Sign::Zero) if holds => Some((Sign::Negative, Sign::Zero)),
+            (None, Sign::Zero, Sign::Unknown) if holds => Some((Sign::Zero, Sign::Positive)),
+            _ => Some((*self, *other))
+        }
+    }
+}
+
+/// A range of numbers, both bounds included. The range of all numbers is `[i64::MIN, i64::MAX]`,
+/// no number can lie outside of it.
+#[derive(Clone,Copy,PartialEq,Eq)]
+pub struct Interval {
+    pub lo: i64,
+    pub hi: i64
+}
+
+impl Debug for Interval {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+impl Interval {
+    /// The smallest and the largest product of numbers from both intervals, and wether any
+    /// product overflows.
+    fn products(&self, other: &Interval) -> (i64, i64, bool) {
+        let corners = [
+            self.lo.checked_mul(other.lo), self.lo.checked_mul(other.hi),
+            self.hi.checked_mul(other.lo), self.hi.checked_mul(other.hi)
+        ];
+        let saturated: Vec<i64> = [
+            self.lo.saturating_mul(other.lo), self.lo.saturating_mul(other.hi),
+            self.hi.saturating_mul(other.lo), self.hi.saturating_mul(other.hi)
+        ].iter().cloned().collect();
+        (*saturated.iter().min().unwrap(), *saturated.iter().max().unwrap(), corners.iter().any(|c| c.is_none()))
+    }
+}
+
+impl Lattice for Interval {
+    fn top() -> Interval {
+        Interval { lo: i64::min_value(), hi: i64::max_value() }
+    }
+
+    fn number(n: i64) -> Interval {
+        Interval { lo: n, hi: n }
+    }
+
+    fn join(&self, other: &Interval) -> Interval {
+        Interval { lo: cmp::min(self.lo, other.lo), hi: cmp::max(self.hi, other.hi) }
+    }
+
+    /// A bound that moved is given up on.
+    fn widen(&self, next: &Interval) -> Interval {
+        Interval {
+            lo: if next.lo < self.lo { i64::min_value() } else { self.lo },
+            hi: if next.hi > self.hi { i64::max_value() } else { self.hi }
+        }
+    }
+
+    /// A bound that was given up on is taken from the next approximation.
+    fn narrow(&self, next: &Interval) -> Interval {
+        Interval {
+            lo: if self.lo == i64::min_value() { next.lo } else { self.lo },
+            hi: if self.hi == i64::max_value() { next.hi } else { self.hi }
+        }
+    }
+
+    // Overflowing runs throw, so the sum of those that complete is within the saturated bounds.
+    fn add(&self, other: &Interval) -> Interval {
+        Interval { lo: self.lo.saturating_add(other.lo), hi: self.hi.saturating_add(other.hi) }
+    }
+
+    fn multiply(&self, other: &Interval) -> Interval {
+        let (lo, hi, _) = self.products(other);
+        Interval { lo: lo, hi: hi }
+    }
+
+    fn add_may_overflow(&self, other: &Interval) -> bool {
+        self.lo.checked_add(other.lo).is_none() || self.hi.checked_add(other.hi).is_none()
+    }
+
+    fn multiply_may_overflow(&self, other: &Interval) -> bool {
+        self.products(other).2
+    }
+
+    fn less_than(&self, other: &Interval) -> Option<bool> {
+        if self.hi < other.lo {
+            Some(true)
+        } else if self.lo >= other.hi {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    fn assume_less_than(&self, other: &Interval, holds: bool) -> Option<(Interval, Interval)> {
+        match self.less_than(other) {
+            Some(result) if result != holds => None,
+            _ if holds => Some((
+                Interval { lo: self.lo, hi: cmp::min(self.hi, other.hi - 1) },
+                Interval { lo: cmp::max(other.lo, self.lo + 1), hi: other.hi }
+            )),
+            _ => Some((
+                Interval { lo: cmp::max(self.lo, other.lo), hi: self.hi },
+                Interval { lo: other.lo, hi: cmp::min(other.hi, self.hi) }
+            ))
+        }
+    }
+}
+
+/// Something the analysis found to be worth a look, with the way from the whole program to the
+/// element, named like the path of an `uninitialized::UnassignedRead`.
+#[derive(Debug,PartialEq)]
+pub enum Warning {
+    /// The addition or multiplication may overflow.
+    Overflow(Element, Vec<String>),
+    /// The condition of an `if` or `while` always has the same value when it is reached.
+    Condition(Element, bool, Vec<String>)
+}
+
+/// The warnings collected while evaluating. Evaluations that are only approximations, like the
+/// iterations of a loop before its head is stable, don't report anything.
+struct Report {
+    enabled: bool,
+    warnings: Vec<Warning>,
+    /// The way to the element being evaluated.
+    path: Vec<String>
+}
+
+impl Report {
+    fn silent() -> Report {
+        Report { enabled: false, warnings: Vec::new(), path: Vec::new() }
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        if self.enabled && !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Report the condition of the `if` or `while` being evaluated if it has the same value
+    /// every time.
+    fn condition<L>(&mut self, cond: &Element, value: &Abstract<L>) {
+        if let Abstract::Boolean(Some(b)) = *value {
+            let mut path = self.path.clone();
+            path.push("condition".to_string());
+            self.warn(Warning::Condition(cond.clone(), b, path));
+        }
+    }
+}
+
+/// An abstract environment, `None` where no run gets to.
+type State<L> = Option<HashMap<String, Abstract<L>>>;
+
+/// The number of times a loop is narrowed after widening.
+const NARROWING: usize = 2;
+
+/// Evaluate an element on abstract values, starting in the given abstract environment.
+///
+/// Returns the abstract value of the element and the abstract environment it ends with, or
+/// `None` if it never completes normally. Statements have an `Unknown` value.
+pub fn abstract_eval<L: Lattice>(element: &Element, environment: &HashMap<String, Abstract<L>>)
+                                 -> Option<(Abstract<L>, HashMap<String, Abstract<L>>)> {
+    eval(element, environment.clone(), &mut Report::silent())
+}
+
+/// Evaluate an element on abstract values like `abstract_eval`, reporting every addition or
+/// multiplication that may overflow and every condition of an `if` or `while` that always has
+/// the same value, in the order they are evaluated in. Each is reported once, even if it is
+/// evaluated several times.
+pub fn analyze<L: Lattice>(element: &Element, environment: &HashMap<String, Abstract<L>>) -> Vec<Warning> {
+    let mut report = Report { enabled: true, warnings: Vec::new(), path: Vec::new() };
+    eval(element, environment.clone(), &mut report);
+    report.warnings
+}
+
+fn eval<L: Lattice>(element: &Element, mut env: HashMap<String, Abstract<L>>, report: &mut Report)
+                    -> Option<(Abstract<L>, HashMap<String, Abstract<L>>)> {
+    match *element {
+        Element::Number(_) | Element::Boolean(_) => Some((Abstract::of(element), env)),
+        Element::Variable(ref name) => {
+            let val = env.get(name).cloned().unwrap_or(Abstract::Unknown);
+            Some((val, env))
+        },
+        Element::Add(ref l, ref r) | Element::Multiply(ref l, ref r) | Element::LessThan(ref l, ref r) => {
+            let (l, env) = match eval_child(l, "left", env, report) { Some(result) => result, None => return None };
+            let (r, env) = match eval_child(r, "right", env, report) { Some(result) => result, None => return None };
+            let (l, r) = (l.number(), r.number());
+            let path = report.path.clone();
+            let val = match *element {
+                Element::Add(..) => {
+                    if l.add_may_overflow(&r) {
+                        report.warn(Warning::Overflow(element.clone(), path));
+                    }
+                    Abstract::Number(l.add(&r))
+                },
+                Element::Multiply(..) => {
+                    if l.multiply_may_overflow(&r) {
+                        report.warn(Warning::Overflow(element.clone(), path));
+                    }
+                    Abstract::Number(l.multiply(&r))
+                },
+                _ => Abstract::Boolean(l.less_than(&r))
+            };
+            Some((val, env))
+        },
+        Element::Assign(ref name, ref val) => {
+            eval_child(val, "value", env, report).map(|(val, mut env)| {
+                env.insert(name.clone(), val);
+                (Abstract::Unknown, env)
+            })
+        },
+        Element::Sequence(ref first, ref second) => {
+            eval_child(first, "first", env, report).and_then(|(_, env)| eval_child(second, "second", env, report))
+        },
+        Element::IfElse(ref cond, ref cons, ref alt) => {
+            let (taken, env) = match eval_child(cond, "condition", env, report) { Some(result) => result, None => return None };
+            report.condition(cond, &taken);
+            let cons = if branch(&taken, true) {
+                assume(cond, true, env.clone()).and_then(|env| eval_child(cons, "consequence", env, report))
+            } else {
+                None
+            };
+            let alt = if branch(&taken, false) {
+                assume(cond, false, env).and_then(|env| eval_child(alt, "alternative", env, report))
+            } else {
+                None
+            };
+            match (cons, alt) {
+                (Some((a, env_a)), Some((b, env_b))) => Some((a.join(&b), join(Some(env_a), Some(env_b)).unwrap())),
+                (cons, alt) => cons.or(alt)
+            }
+        },
+        Element::While(ref cond, ref body, _) if !body.may_jump() => {
+            let initial = Some(env);
+            let mut head = initial.clone();
+            let mut silent = Report::silent();
+            loop {
+                let next = join(initial.clone(), iterate(cond, body, head.clone(), &mut silent));
+                let widened = widen(head.clone(), next);
+                if widened == head {
+                    break;
+                }
+                head = widened;
+            }
+            for _ in 0..NARROWING {
+                let next = join(initial.clone(), iterate(cond, body, head.clone(), &mut silent));
+                head = narrow(head, next);
+            }
+
+            // Once more with the stable head, for the report.
+            iterate(cond, body, head.clone(), report);
+
+            head.and_then(|env| eval_child(cond, "condition", env, report))
+                .and_then(|(taken, env)| if branch(&taken, false) { assume(cond, false, env) } else { None })
+                .map(|env| (Abstract::Unknown, env))
+        },
+        Element::Print(ref val) => eval_child(val, "value", env, report).map(|(_, env)| (Abstract::Unknown, env)),
+        Element::Read(ref name) => {
+            env.insert(name.clone(), Abstract::Unknown);
+            Some((Abstract::Unknown, env))
+        },
+        Element::Assert(ref cond, _) => {
+            eval_child(cond, "condition", env, report)
+                .and_then(|(holds, env)| if branch(&holds, true) { assume(cond, true, env) } else { None })
+                .map(|env| (Abstract::Unknown, env))
+        },
+        Element::Throw(_) | Element::Break | Element::Continue => None,
+        _ => {
+            let mut written = Vec::new();
+            element.written(&mut written);
+            for name in written.into_iter() {
+                env.insert(name, Abstract::Unknown);
+            }
+            Some((Abstract::Unknown, env))
+        }
+    }
+}
+
+/// Evaluate a child of the element being evaluated, the label is its part of the path.
+fn eval_child<L: Lattice>(element: &Element, label: &str, env: HashMap<String, Abstract<L>>, report: &mut Report)
+                          -> Option<(Abstract<L>, HashMap<String, Abstract<L>>)> {
+    report.path.push(label.to_string());
+    let result = eval(element, env, report);
+    report.path.pop();
+    result
+}
+
+/// Wether a condition with the given value may take the branch for `holds`.
+fn branch<L>(cond: &Abstract<L>, holds: bool) -> bool {
+    match *cond {
+        Abstract::Boolean(Some(b)) => b == holds,
+        Abstract::Boolean(None) | Abstract::Unknown => true,
+        // A number as condition is a type mismatch.
+        Abstract::Number(_) => false
+    }
+}
+
+/// The environment after evaluating the condition of a loop and its body once.
+fn iterate<L: Lattice>(cond: &Element, body: &Element, head: State<L>, report: &mut Report) -> State<L> {
+    head.and_then(|env| eval_child(cond, "condition", env, report))
+        .and_then(|(taken, env)| {
+            report.condition(cond, &taken);
+            if branch(&taken, true) { assume(cond, true, env) } else { None }
+        })
+        .and_then(|env| eval_child(body, "body", env, report))
+        .map(|(_, env)| env)
+}
+
+/// Restrict an environment, in which the condition was just evaluated, to the runs in which it
+/// resulted in `holds`. Only conditions free of side effects are looked into.
+fn assume<L: Lattice>(cond: &Element, holds: bool, mut env: HashMap<String, Abstract<L>>) -> State<L> {
+    match *cond {
+        Element::Boolean(b) => if b == holds { Some(env) } else { None },
+        Element::Variable(ref name) => {
+            match env.get(name).cloned() {
+                Some(Abstract::Boolean(Some(b))) if b != holds => return None,
+                Some(Abstract::Boolean(None)) => { env.insert(name.clone(), Abstract::Boolean(Some(holds))); },
+                _ => {}
+            }
+            Some(env)
+        },
+        Element::LessThan(ref l, ref r) if pure(l) && pure(r) => {
+            let l_val = eval(l, env.clone(), &mut Report::silent()).unwrap().0.number();
+            let r_val = eval(r, env.clone(), &mut Report::silent()).unwrap().0.number();
+            let (l_val, r_val) = match l_val.assume_less_than(&r_val, holds) {
+                Some(restricted) => restricted,
+                None => return None
+            };
+            for &(operand, ref val) in [(l, l_val), (r, r_val)].iter() {
+                // Only numbers are restricted, booleans compare as numbers but stay booleans.
+                if let Element::Variable(ref name) = **operand {
+                    if let Some(&Abstract::Number(_)) = env.get(name) {
+                        env.insert(name.clone(), Abstract::Number(val.clone()));
+                    }
+                }
+            }
+            Some(env)
+        },
+        _ => Some(env)
+    }
+}
+
+/// Wether the element is an expression free of side effects, whose evaluation always completes.
+fn pure(element: &Element) -> bool {
+    match *element {
+        Element::Number(_) | Element::Boolean(_) | Element::Variable(_) => true,
+        Element::Add(ref l, ref r) | Element::Multiply(ref l, ref r) | Element::LessThan(ref l, ref r) => {
+            pure(l) && pure(r)
+        },
+        _ => false
+    }
+}
+
+/// Combine two abstract environments variable by variable. A variable set in only one of them
+/// may not be set at all in the other, and could be anything.
+fn combine<L, F>(a: State<L>, b: State<L>, f: F) -> State<L>
+    where L: Lattice, F: Fn(&Abstract<L>, &Abstract<L>) -> Abstract<L> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let mut combined = HashMap::new();
+            for (name, val) in a.iter() {
+                combined.insert(name.clone(), match b.get(name) {
+                    Some(other) => f(val, other),
+                    None => Abstract::Unknown
+                });
+            }
+            for name in b.keys() {
+                if !a.contains_key(name) {
+                    combined.insert(name.clone(), Abstract::Unknown);
+                }
+            }
+            Some(combined)
+        },
+        (a, b) => a.or(b)
+    }
+}
+
+fn join<L: Lattice>(a: State<L>, b: State<L>) -> State<L> {
+    combine(a, b, |a, b| a.join(b))
+}
+
+fn widen<L: Lattice>(a: State<L>, b: State<L>) -> State<L> {
+    combine(a, b, |a, b| a.widen(b))
+}
+
+fn narrow<L: Lattice>(a: State<L>, b: State<L>) -> State<L> {
+    match (a, b) {
+        (Some(a), Some(b)) => combine(Some(a), Some(b), |a, b| a.narrow(b)),
+        // No run gets to the loop head after all.
+        _ => None
+    }
+}
+
+#[cfg(test)]
+fn path(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_sign_arithmetic() {
+    assert_eq!(Sign::Positive, Sign::Negative.multiply(&Sign::Negative));
+    assert_eq!(Sign::Zero, Sign::Unknown.multiply(&Sign::Zero));
+    assert_eq!(Sign::Unknown, Sign::Negative.add(&Sign::Positive));
+    assert_eq!(Sign::Negative, Sign::Negative.add(&Sign::Zero));
+    assert_eq!(Some(true), Sign::Negative.less_than(&Sign::Positive));
+    assert_eq!(None, Sign::Positive.less_than(&Sign::Positive));
+}
+
+#[test]
+fn test_abstract_eval_signs() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), Abstract::Number(Sign::Negative));
+    env.insert("y".to_string(), Abstract::Number(Sign::Unknown));
+
+    let (val, after) = abstract_eval(&sequence!(
+        assign!("z", multiply!(variable!("x"), variable!("x"))),
+        add!(variable!("z"), multiply!(variable!("y"), number!(0)))
+        ), &env).unwrap();
+    assert_eq!(Abstract::Number(Sign::Positive), val);
+    assert_eq!(Some(&Abstract::Number(Sign::Positive)), after.get("z"));
+
+    // Either branch may be taken, `z` ends up zero or positive.
+    let (_, after) = abstract_eval(&sequence!(
+        assign!("z", number!(0)),
+        if_!(less_than!(number!(0), variable!("y")), assign!("z", variable!("y")))
+        ), &env).unwrap();
+    assert_eq!(Some(&Abstract::Number(Sign::Unknown)), after.get("z"));
+
+    // Only one branch can be taken.
+    let (_, after) = abstract_eval(&ifelse!(
+        less_than!(variable!("x"), number!(0)),
+        assign!("z", number!(1)),
+        assign!("z", number!(-1))
+        ), &env).unwrap();
+    assert_eq!(Some(&Abstract::Number(Sign::Positive)), after.get("z"));
+
+    // `y` is positive in the first branch.
+    let (_, after) = abstract_eval(&ifelse!(
+        less_than!(number!(0), variable!("y")),
+        assign!("z", variable!("y")),
+        assign!("z", number!(1))
+        ), &env).unwrap();
+    assert_eq!(Some(&Abstract::Number(Sign::Positive)), after.get("z"));
+}
+
+#[test]
+fn test_abstract_eval_loops() {
+    let program = sequence!(
+        assign!("x", number!(1)),
+        while_!(less_than!(variable!("x"), number!(100)), assign!("x", multiply!(variable!("x"), number!(2))))
+        );
+    let (_, env) = abstract_eval::<Sign>(&program, &HashMap::new()).unwrap();
+    assert_eq!(Some(&Abstract::Number(Sign::Positive)), env.get("x"));
+
+    // Never completes.
+    let program = while_!(boolean!(true), assign!("x", number!(1)));
+    assert_eq!(None, abstract_eval::<Sign>(&program, &HashMap::new()));
+
+    // The body never runs.
+    let program = sequence!(
+        assign!("x", number!(-1)),
+        while_!(less_than!(number!(0), variable!("x")), assign!("x", number!(0)))
+        );
+    let (_, env) = abstract_eval::<Sign>(&program, &HashMap::new()).unwrap();
+    assert_eq!(Some(&Abstract::Number(Sign::Negative)), env.get("x"));
+
+    // With `break` the loop isn't looked into.
+    let program = sequence!(
+        assign!("x", number!(1)),
+        while_!(boolean!(true), sequence!(assign!("x", number!(-1)), break_!()))
+        );
+    let (_, env) = abstract_eval::<Sign>(&program, &HashMap::new()).unwrap();
+    assert_eq!(Some(&Abstract::Unknown), env.get("x"));
+}
+
+#[test]
+fn test_abstract_eval_covers_machine() {
+    use Machine;
+
+    let program = sequence!(
+        assign!("y", number!(0)),
+        while_!(
+            less_than!(number!(0), variable!("x")),
+            sequence!(
+                assign!("y", add!(variable!("y"), variable!("x"))),
+                assign!("x", add!(variable!("x"), number!(-1)))
+                )
+            )
+        );
+
+    for x in -2..4 {
+        let mut env = HashMap::new();
+        env.insert("x".to_string(), number!(x));
+
+        let (_, abstract_env) = abstract_eval::<Sign>(&program, &abstraction(&env)).unwrap();
+        let mut m = Machine::new(program.clone(), env);
+        m.run();
+        for (name, val) in abstraction::<Sign>(&m.clone_env()).into_iter() {
+            let covering = abstract_env.get(&name).unwrap();
+            assert_eq!(covering, &val.join(covering));
+        }
+    }
+}
+
+#[test]
+fn test_interval_loops_are_widened_and_narrowed() {
+    let program = sequence!(
+        assign!("i", number!(0)),
+        sequence!(
+            assign!("sum", number!(0)),
+            while_!(
+                less_than!(variable!("i"), number!(10)),
+                sequence!(
+                    assign!("sum", add!(variable!("sum"), variable!("i"))),
+                    assign!("i", add!(variable!("i"), number!(1)))
+                    )
+                )
+            )
+        );
+
+    let (_, env) = abstract_eval::<Interval>(&program, &HashMap::new()).unwrap();
+    assert_eq!(Some(&Abstract::Number(Interval { lo: 10, hi: 10 })), env.get("i"));
+    assert_eq!(Some(&Abstract::Number(Interval { lo: 0, hi: i64::max_value() })), env.get("sum"));
+
+    // `i + 1` stays below 10, `sum` is unbounded.
+    assert_eq!(
+        vec![Warning::Overflow(*add!(variable!("sum"), variable!("i")), path(&["second", "second", "body", "first", "value"]))],
+        analyze::<Interval>(&program, &HashMap::new()));
+}
+
+#[test]
+fn test_analyze_reports_overflow() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), Abstract::Number(Interval { lo: -5, hi: 5 }));
+    env.insert("big".to_string(), Abstract::Number(Interval { lo: 0, hi: i64::max_value() / 2 }));
+
+    assert_eq!(
+        Vec::<Warning>::new(),
+        analyze(&add!(multiply!(variable!("x"), variable!("x")), variable!("big")), &env));
+    assert_eq!(
+        vec![Warning::Overflow(*multiply!(variable!("big"), number!(3)), path(&[]))],
+        analyze(&multiply!(variable!("big"), number!(3)), &env));
+
+    // Doubling until it is not positive anymore can only end by overflowing.
+    let program = sequence!(
+        assign!("y", number!(1)),
+        while_!(less_than!(number!(0), variable!("y")), assign!("y", multiply!(variable!("y"), number!(2))))
+        );
+    assert_eq!(
+        vec![
+            Warning::Condition(*less_than!(number!(0), variable!("y")), true, path(&["second", "condition"])),
+            Warning::Overflow(*multiply!(variable!("y"), number!(2)), path(&["second", "body", "value"]))
+        ],
+        analyze::<Interval>(&program, &HashMap::new()));
+    assert_eq!(None, abstract_eval::<Interval>(&program, &HashMap::new()));
+
+    // Signs know that adding numbers of different signs is safe.
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), Abstract::Number(Sign::Negative));
+    assert_eq!(Vec::<Warning>::new(), analyze(&add!(variable!("x"), number!(1)), &env));
+}
+
+#[test]
+fn test_analyze_reports_constant_conditions() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), Abstract::Number(Interval { lo: 0, hi: 9 }));
+
+    let program = sequence!(
+        if_!(less_than!(variable!("x"), number!(0)), assign!("y", number!(1))),
+        sequence!(
+            if_!(less_than!(variable!("x"), number!(5)), assign!("y", number!(2))),
+            while_!(less_than!(variable!("x"), number!(10)), assign!("x", add!(variable!("x"), number!(1))))
+            )
+        );
+    assert_eq!(
+        vec![Warning::Condition(*less_than!(variable!("x"), number!(0)), false, path(&["first", "condition"]))],
+        analyze(&program, &env));
+
+    // The loop never ends, its condition is always true.
+    let program = while_!(less_than!(variable!("x"), number!(10)), assign!("y", variable!("x")));
+    assert_eq!(
+        vec![Warning::Condition(*less_than!(variable!("x"), number!(10)), true, path(&["condition"]))],
+        analyze(&program, &env));
+}
+
+#[test]
+fn test_analyze_tells_equal_expressions_apart() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), Abstract::Number(Interval { lo: 0, hi: i64::max_value() }));
+
+    // Only the second `x + 1` can overflow.
+    let program = ifelse!(
+        less_than!(variable!("x"), number!(10)),
+        assign!("y", add!(variable!("x"), number!(1))),
+        assign!("z", add!(variable!("x"), number!(1)))
+        );
+    assert_eq!(
+        vec![Warning::Overflow(*add!(variable!("x"), number!(1)), path(&["alternative", "value"]))],
+        analyze(&program, &env));
+}
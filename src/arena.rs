@@ -0,0 +1,435 @@
+//! An arena-allocated, hash-consed representation of SIMPLE programs.
+//!
+//! Reducing an `Element` clones every part of the expression it does not touch, so each step
+//! takes time proportional to the size of the whole program. Here every node lives in an arena
+//! and refers to its children by index. Equal nodes are stored only once, so a step only creates
+//! the few nodes on the path to the reduced part, everything else is shared.
+//!
+//! This covers the SIMPLE language of the book: numbers, booleans, `+`, `*`, `<`, variables,
+//! assignments, sequences, `if`, `while` and `do-nothing`, plus the errors thrown by failing
+//! operations. Converting any other element gives `None`.
+
+use std::collections::hash_map::HashMap;
+
+use Element;
+
+/// The index of a node in an arena.
+#[derive(Clone,Copy,PartialEq,Eq,Hash,Debug)]
+pub struct Id(usize);
+
+/// Like `Element`, but refering to children by their index in the arena.
+#[derive(Clone,PartialEq,Eq,Hash)]
+enum Node {
+    Number(i64),
+    Add(Id, Id),
+    Multiply(Id, Id),
+    Boolean(bool),
+    LessThan(Id, Id),
+    Variable(String),
+    Assign(String, Id),
+    Sequence(Id, Id),
+    IfElse(Id, Id, Id),
+    While(Id, Id),
+    Throw(Id),
+    Error(String),
+    DoNothing
+}
+
+/// Insert an element into the arena, returning `None` from the current function if that fails.
+macro_rules! insert(
+    ($arena:expr, $element:expr) => (
+        match $arena.insert($element) {
+            Some(id) => id,
+            None => return None
+        }
+    )
+);
+
+/// Stores every distinct node exactly once.
+pub struct Arena {
+    nodes: Vec<Node>,
+    ids: HashMap<Node, Id>
+}
+
+impl Arena {
+    /// Create an empty arena
+    pub fn new() -> Arena {
+        Arena {
+            nodes: Vec::new(),
+            ids: HashMap::new()
+        }
+    }
+
+    /// The number of distinct nodes stored
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The index of a node, storing it first if it is new.
+    fn intern(&mut self, node: Node) -> Id {
+        match self.ids.get(&node) {
+            Some(&id) => return id,
+            None => {}
+        }
+
+        let id = Id(self.nodes.len());
+        self.nodes.push(node.clone());
+        self.ids.insert(node, id);
+        id
+    }
+
+    /// Store an element. `None` if it uses more than the SIMPLE core.
+    pub fn insert(&mut self, element: &Element) -> Option<Id> {
+        let node = match *element {
+            Element::Number(n) => Node::Number(n),
+            Element::Add(ref l, ref r) => Node::Add(insert!(self, l), insert!(self, r)),
+            Element::Multiply(ref l, ref r) => Node::Multiply(insert!(self, l), insert!(self, r)),
+            Element::Boolean(b) => Node::Boolean(b),
+            Element::LessThan(ref l, ref r) => Node::LessThan(insert!(self, l), insert!(self, r)),
+            Element::Variable(ref name) => Node::Variable(name.clone()),
+            Element::Assign(ref name, ref val) => Node::Assign(name.clone(), insert!(self, val)),
+            Element::Sequence(ref first, ref second) => {
+                Node::Sequence(insert!(self, first), insert!(self, second))
+            },
+            Element::IfElse(ref cond, ref cons, ref alt) => {
+                Node::IfElse(insert!(self, cond), insert!(self, cons), insert!(self, alt))
+            },
+            Element::While(ref cond, ref body, None) => Node::While(insert!(self, cond), insert!(self, body)),
+            Element::Throw(ref val) => Node::Throw(insert!(self, val)),
+            Element::Error(ref message) => Node::Error(message.clone()),
+            Element::DoNothing => Node::DoNothing,
+            _ => return None
+        };
+        Some(self.intern(node))
+    }
+
+    /// Build the element stored at an index
+    pub fn element(&self, id: Id) -> Element {
+        match self.nodes[id.0] {
+            Node::Number(n) => Element::Number(n),
+            Node::Add(l, r) => Element::Add(box self.element(l), box self.element(r)),
+            Node::Multiply(l, r) => Element::Multiply(box self.element(l), box self.element(r)),
+            Node::Boolean(b) => Element::Boolean(b),
+            Node::LessThan(l, r) => Element::LessThan(box self.element(l), box self.element(r)),
+            Node::Variable(ref name) => Element::Variable(name.clone()),
+            Node::Assign(ref name, val) => Element::Assign(name.clone(), box self.element(val)),
+            Node::Sequence(first, second) => {
+                Element::Sequence(box self.element(first), box self.element(second))
+            },
+            Node::IfElse(cond, cons, alt) => {
+                Element::IfElse(box self.element(cond), box self.element(cons), box self.element(alt))
+            },
+            Node::While(cond, body) => Element::While(box self.element(cond), box self.element(body), None),
+            Node::Throw(val) => Element::Throw(box self.element(val)),
+            Node::Error(ref message) => Element::Error(message.clone()),
+            Node::DoNothing => Element::DoNothing
+        }
+    }
+
+    /// Wether or not the node at an index is reducible, just like `Element::is_reducible`.
+    pub fn is_reducible(&self, id: Id) -> bool {
+        match self.nodes[id.0] {
+            Node::Number(_) | Node::Boolean(_) | Node::Error(_) | Node::DoNothing => false,
+            Node::Throw(val) => self.is_reducible(val) || self.is_thrown(val),
+            _ => true
+        }
+    }
+
+    fn is_thrown(&self, id: Id) -> bool {
+        match self.nodes[id.0] {
+            Node::Throw(_) => !self.is_reducible(id),
+            _ => false
+        }
+    }
+
+    fn number(&self, id: Id) -> Option<i64> {
+        match self.nodes[id.0] {
+            Node::Number(n) => Some(n),
+            Node::Boolean(true) => Some(1),
+            Node::Boolean(false) => Some(0),
+            _ => None
+        }
+    }
+
+    fn operands(&self, id: Id) -> Vec<Id> {
+        match self.nodes[id.0] {
+            Node::Add(l, r) | Node::Multiply(l, r) | Node::LessThan(l, r) => vec![l, r],
+            Node::Assign(_, val) => vec![val],
+            Node::Sequence(first, _) => vec![first],
+            Node::IfElse(cond, _, _) => vec![cond],
+            Node::Throw(val) => vec![val],
+            _ => vec![]
+        }
+    }
+
+    fn raise(&mut self, message: String) -> Id {
+        let error = self.intern(Node::Error(message));
+        self.intern(Node::Throw(error))
+    }
+
+    fn type_mismatch(&mut self, id: Id) -> Id {
+        let message = format!("type mismatch in {:?}", self.element(id));
+        self.raise(message)
+    }
+
+    fn overflow(&mut self, id: Id) -> Id {
+        let message = format!("overflow in {:?}", self.element(id));
+        self.raise(message)
+    }
+
+    /// Reduce the node at an index by one step, just like `Element::reduce`.
+    pub fn reduce(&mut self, id: Id, environment: &mut HashMap<String, Id>) -> Id {
+        // A thrown value in the next operand to reduce aborts the whole node.
+        match self.operands(id).into_iter().find(|&o| self.is_reducible(o) || self.is_thrown(o)) {
+            Some(thrown) if self.is_thrown(thrown) => return thrown,
+            _ => {}
+        }
+
+        match self.nodes[id.0].clone() {
+            Node::Add(l, r) => {
+                if self.is_reducible(l) {
+                    let l = self.reduce(l, environment);
+                    self.intern(Node::Add(l, r))
+                } else if self.is_reducible(r) {
+                    let r = self.reduce(r, environment);
+                    self.intern(Node::Add(l, r))
+                } else {
+                    match (self.number(l), self.number(r)) {
+                        (Some(l), Some(r)) => match l.checked_add(r) {
+                            Some(sum) => self.intern(Node::Number(sum)),
+                            None => self.overflow(id)
+                        },
+                        _ => self.type_mismatch(id)
+                    }
+                }
+            },
+            Node::Multiply(l, r) => {
+                if self.is_reducible(l) {
+                    let l = self.reduce(l, environment);
+                    self.intern(Node::Multiply(l, r))
+                } else if self.is_reducible(r) {
+                    let r = self.reduce(r, environment);
+                    self.intern(Node::Multiply(l, r))
+                } else {
+                    match (self.number(l), self.number(r)) {
+                        (Some(l), Some(r)) => match l.checked_mul(r) {
+                            Some(product) => self.intern(Node::Number(product)),
+                            None => self.overflow(id)
+                        },
+                        _ => self.type_mismatch(id)
+                    }
+                }
+            },
+            Node::LessThan(l, r) => {
+                if self.is_reducible(l) {
+                    let l = self.reduce(l, environment);
+                    self.intern(Node::LessThan(l, r))
+                } else if self.is_reducible(r) {
+                    let r = self.reduce(r, environment);
+                    self.intern(Node::LessThan(l, r))
+                } else {
+                    match (self.number(l), self.number(r)) {
+                        (Some(l), Some(r)) => self.intern(Node::Boolean(l < r)),
+                        _ => self.type_mismatch(id)
+                    }
+                }
+            },
+            Node::Variable(ref name) => {
+                match environment.get(name) {
+                    Some(&val) => val,
+                    None => self.intern(Node::DoNothing)
+                }
+            },
+            Node::Assign(ref name, val) => {
+                if self.is_reducible(val) {
+                    let val = self.reduce(val, environment);
+                    self.intern(Node::Assign(name.clone(), val))
+                } else {
+                    environment.insert(name.clone(), val);
+                    self.intern(Node::DoNothing)
+                }
+            },
+            Node::Sequence(first, second) => {
+                if self.nodes[first.0] == Node::DoNothing {
+                    second
+                } else {
+                    let first = self.reduce(first, environment);
+                    self.intern(Node::Sequence(first, second))
+                }
+            },
+            Node::IfElse(cond, cons, alt) => {
+                match self.nodes[cond.0] {
+                    Node::Boolean(true) => cons,
+                    Node::Boolean(false) => alt,
+                    _ if self.is_reducible(cond) => {
+                        let cond = self.reduce(cond, environment);
+                        self.intern(Node::IfElse(cond, cons, alt))
+                    },
+                    _ => self.type_mismatch(id)
+                }
+            },
+            Node::While(cond, body) => {
+                let again = self.intern(Node::Sequence(body, id));
+                let nothing = self.intern(Node::DoNothing);
+                self.intern(Node::IfElse(cond, again, nothing))
+            },
+            Node::Throw(val) => {
+                let val = self.reduce(val, environment);
+                self.intern(Node::Throw(val))
+            },
+            Node::DoNothing => id,
+            _ => self.type_mismatch(id)
+        }
+    }
+}
+
+/// A virtual machine like `Machine`, running on an arena.
+pub struct ArenaMachine {
+    arena: Arena,
+    expression: Id,
+    environment: HashMap<String, Id>
+}
+
+impl ArenaMachine {
+    /// Create a new machine with a given expression and an environment.
+    /// `None` if either of them uses more than the SIMPLE core.
+    pub fn new(expression: Box<Element>, map: HashMap<String, Box<Element>>) -> Option<ArenaMachine> {
+        let mut arena = Arena::new();
+        let mut environment = HashMap::new();
+        for (name, val) in map.into_iter() {
+            environment.insert(name, insert!(arena, &val));
+        }
+        let expression = insert!(arena, &expression);
+
+        Some(ArenaMachine {
+            arena: arena,
+            expression: expression,
+            environment: environment
+        })
+    }
+
+    /// The current expression. Building it takes time proportional to its size.
+    pub fn expression(&self) -> Element {
+        self.arena.element(self.expression)
+    }
+
+    /// The current environment
+    pub fn clone_env(&self) -> HashMap<String, Box<Element>> {
+        self.environment.iter().map(|(name, &val)| (name.clone(), box self.arena.element(val))).collect()
+    }
+
+    /// Wether or not the current expression is reducible
+    pub fn is_reducible(&self) -> bool {
+        self.arena.is_reducible(self.expression)
+    }
+
+    /// Reduce one step of our current expression
+    pub fn step(&mut self) {
+        self.expression = self.arena.reduce(self.expression, &mut self.environment);
+    }
+
+    /// Reduce until we reached a non-reducible expression.
+    /// Unlike `Machine::run` this does not print every step, that would take time proportional
+    /// to the size of the program again.
+    pub fn run(&mut self) {
+        while self.is_reducible() {
+            self.step();
+        }
+    }
+}
+
+#[test]
+fn test_equal_elements_are_stored_once() {
+    let mut arena = Arena::new();
+
+    let a = arena.insert(&add!(number!(1), number!(1))).unwrap();
+    assert_eq!(2, arena.len());
+
+    let b = arena.insert(&add!(number!(1), number!(1))).unwrap();
+    assert_eq!(a, b);
+    assert_eq!(2, arena.len());
+    assert_eq!("1 + 1".to_string(), format!("{:?}", arena.element(a)));
+}
+
+#[test]
+fn test_unsupported_elements_are_rejected() {
+    let mut arena = Arena::new();
+
+    assert_eq!(None, arena.insert(&list!(number!(1))));
+    assert_eq!(None, arena.insert(&sequence!(break_!(), box Element::DoNothing)));
+    assert!(ArenaMachine::new(print_!(number!(1)), HashMap::new()).is_none());
+}
+
+#[test]
+fn test_arena_steps_like_elements() {
+    use Machine;
+
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+
+    //  x = 0; while (x < 5) [ if (x < 3) [ x = x + 1 ] else [ x = x * 3 ] ]; y = x + true
+    let program = sequence!(
+        while_!(
+            less_than!(variable!("x"), number!(5)),
+            ifelse!(
+                less_than!(variable!("x"), number!(3)),
+                assign!("x", add!(variable!("x"), number!(1))),
+                assign!("x", multiply!(variable!("x"), number!(3)))
+                )
+            ),
+        assign!("y", add!(variable!("x"), boolean!(true)))
+        );
+
+    let mut m = Machine::new(program.clone(), env.clone());
+    let mut a = ArenaMachine::new(program, env).unwrap();
+
+    while m.expression().is_reducible() {
+        assert_eq!(format!("{:?}", m.expression()), format!("{:?}", a.expression()));
+        assert_eq!(true, a.is_reducible());
+        m.step();
+        a.step();
+    }
+
+    assert_eq!(false, a.is_reducible());
+    assert_eq!(m.clone_env(), a.clone_env());
+    assert_eq!(10, a.clone_env().get("y").unwrap().value());
+}
+
+#[test]
+fn test_arena_throws_like_elements() {
+    let mut a = ArenaMachine::new(
+        sequence!(assign!("x", less_than!(number!(1), variable!("z"))), assign!("y", number!(1))),
+        HashMap::new()
+        ).unwrap();
+
+    a.run();
+
+    assert_eq!(
+        "throw error(\"type mismatch in 1 < do-nothing\")".to_string(),
+        format!("{:?}", a.expression()));
+    assert_eq!(0, a.clone_env().len());
+}
+
+#[test]
+fn test_arena_step_shares_untouched_nodes() {
+    let mut env = HashMap::new();
+    env.insert("i".to_string(), number!(0));
+
+    // A long loop body that is never run does not get copied on every unfolding.
+    let mut unused = box Element::DoNothing;
+    for n in 0..100 {
+        unused = sequence!(assign!("unused", number!(n)), unused);
+    }
+    let program = while_!(
+        less_than!(variable!("i"), number!(1000)),
+        ifelse!(boolean!(false), unused, assign!("i", add!(variable!("i"), number!(1))))
+        );
+
+    let mut a = ArenaMachine::new(program, env).unwrap();
+    let before = a.arena.len();
+    a.run();
+
+    assert_eq!(1000, a.clone_env().get("i").unwrap().value());
+    // A handful of new nodes for every iteration, regardless of the size of the body.
+    assert!(a.arena.len() - before < 1000 * 10);
+}
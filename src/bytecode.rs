@@ -0,0 +1,303 @@
+//! A bytecode compiler and stack-based virtual machine for SIMPLE.
+//!
+//! When only the final result matters, stepping through every reduction is wasteful. Programs
+//! are compiled to a flat list of instructions instead and executed on a stack. Every element
+//! leaves exactly one value on the stack: expressions their result, statements `do-nothing`.
+//!
+//! Only the SIMPLE language of the book is compiled: numbers, booleans, `+`, `*`, `<`,
+//! variables, assignments, sequences, `if`, `while` and `do-nothing`.
+
+use std::collections::hash_map::HashMap;
+
+use Element;
+
+/// A single instruction of the stack machine.
+#[derive(Clone,PartialEq,Debug)]
+pub enum Instruction {
+    /// Push a constant.
+    Push(Element),
+    /// Push the value of a variable, `do-nothing` if it is not set.
+    Load(String),
+    /// Pop a value and assign it to a variable.
+    Store(String),
+    /// Pop a value and forget about it.
+    Pop,
+    /// Pop two values, push their sum.
+    Add,
+    /// Pop two values, push their product.
+    Multiply,
+    /// Pop two values, push whether the first is less than the second.
+    LessThan,
+    /// Continue at the given instruction.
+    Jump(usize),
+    /// Pop a boolean, continue at the given instruction if it is false.
+    JumpIfFalse(usize)
+}
+
+/// A compiled program.
+#[derive(Clone,PartialEq,Debug)]
+pub struct Program {
+    pub instructions: Vec<Instruction>
+}
+
+/// Compile an element into a program.
+pub fn compile(element: &Element) -> Result<Program, String> {
+    let mut program = Program { instructions: Vec::new() };
+    try!(program.emit(element));
+    Ok(program)
+}
+
+impl Program {
+    /// Append the instructions for an element.
+    fn emit(&mut self, element: &Element) -> Result<(), String> {
+        match *element {
+            Element::Number(_) | Element::Boolean(_) | Element::DoNothing => {
+                self.instructions.push(Instruction::Push(element.clone()));
+            },
+            Element::Add(ref l, ref r) => {
+                try!(self.emit(l));
+                try!(self.emit(r));
+                self.instructions.push(Instruction::Add);
+            },
+            Element::Multiply(ref l, ref r) => {
+                try!(self.emit(l));
+                try!(self.emit(r));
+                self.instructions.push(Instruction::Multiply);
+            },
+            Element::LessThan(ref l, ref r) => {
+                try!(self.emit(l));
+                try!(self.emit(r));
+                self.instructions.push(Instruction::LessThan);
+            },
+            Element::Variable(ref name) => {
+                self.instructions.push(Instruction::Load(name.clone()));
+            },
+            Element::Assign(ref name, ref val) => {
+                try!(self.emit(val));
+                self.instructions.push(Instruction::Store(name.clone()));
+                self.instructions.push(Instruction::Push(Element::DoNothing));
+            },
+            Element::Sequence(ref first, ref second) => {
+                try!(self.emit(first));
+                self.instructions.push(Instruction::Pop);
+                try!(self.emit(second));
+            },
+            Element::IfElse(ref cond, ref cons, ref alt) => {
+                try!(self.emit(cond));
+                let to_alt = self.placeholder();
+                try!(self.emit(cons));
+                let to_end = self.placeholder();
+                self.instructions[to_alt] = Instruction::JumpIfFalse(self.instructions.len());
+                try!(self.emit(alt));
+                self.instructions[to_end] = Instruction::Jump(self.instructions.len());
+            },
+            Element::While(ref cond, ref body, None) => {
+                let start = self.instructions.len();
+                try!(self.emit(cond));
+                let to_end = self.placeholder();
+                try!(self.emit(body));
+                self.instructions.push(Instruction::Pop);
+                self.instructions.push(Instruction::Jump(start));
+                self.instructions[to_end] = Instruction::JumpIfFalse(self.instructions.len());
+                self.instructions.push(Instruction::Push(Element::DoNothing));
+            },
+            _ => return Err(format!("cannot compile {:?}", element))
+        }
+        Ok(())
+    }
+
+    /// Reserve room for a jump whose target is not known yet.
+    fn placeholder(&mut self) -> usize {
+        self.instructions.push(Instruction::Jump(0));
+        self.instructions.len() - 1
+    }
+
+    /// A human-readable listing of the instructions, one per line.
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::new();
+        for (address, instruction) in self.instructions.iter().enumerate() {
+            let text = match *instruction {
+                Instruction::Push(ref val) => format!("push {:?}", val),
+                Instruction::Load(ref name) => format!("load {}", name),
+                Instruction::Store(ref name) => format!("store {}", name),
+                Instruction::Pop => "pop".to_string(),
+                Instruction::Add => "add".to_string(),
+                Instruction::Multiply => "mul".to_string(),
+                Instruction::LessThan => "lt".to_string(),
+                Instruction::Jump(target) => format!("jump {:04}", target),
+                Instruction::JumpIfFalse(target) => format!("jump-if-false {:04}", target)
+            };
+            listing.push_str(&format!("{:04} {}\n", address, text));
+        }
+        listing
+    }
+
+    /// Run the program in an environment and return the value it leaves behind.
+    pub fn run(&self, environment: &mut HashMap<String, Box<Element>>) -> Result<Element, String> {
+        let mut stack: Vec<Element> = Vec::new();
+        let mut pc = 0;
+
+        while pc < self.instructions.len() {
+            let instruction = &self.instructions[pc];
+            pc += 1;
+
+            match *instruction {
+                Instruction::Push(ref val) => stack.push(val.clone()),
+                Instruction::Load(ref name) => {
+                    stack.push(match environment.get(name) {
+                        Some(val) => *val.clone(),
+                        None => Element::DoNothing
+                    });
+                },
+                Instruction::Store(ref name) => {
+                    let val = try!(pop(&mut stack));
+                    environment.insert(name.clone(), box val);
+                },
+                Instruction::Pop => {
+                    try!(pop(&mut stack));
+                },
+                Instruction::Add | Instruction::Multiply | Instruction::LessThan => {
+                    let r = try!(pop(&mut stack));
+                    let l = try!(pop(&mut stack));
+                    let (l, r) = match (number(&l), number(&r)) {
+                        (Some(l), Some(r)) => (l, r),
+                        _ => return Err(format!("type mismatch in {:?} at {:04}", instruction, pc - 1))
+                    };
+                    let result = match *instruction {
+                        Instruction::Add => l.checked_add(r).map(Element::Number),
+                        Instruction::Multiply => l.checked_mul(r).map(Element::Number),
+                        _ => Some(Element::Boolean(l < r))
+                    };
+                    match result {
+                        Some(val) => stack.push(val),
+                        None => return Err(format!("overflow in {:?} at {:04}", instruction, pc - 1))
+                    }
+                },
+                Instruction::Jump(target) => pc = target,
+                Instruction::JumpIfFalse(target) => {
+                    match try!(pop(&mut stack)) {
+                        Element::Boolean(true) => {},
+                        Element::Boolean(false) => pc = target,
+                        _ => return Err(format!("type mismatch in {:?} at {:04}", instruction, pc - 1))
+                    }
+                }
+            }
+        }
+
+        pop(&mut stack)
+    }
+}
+
+fn pop(stack: &mut Vec<Element>) -> Result<Element, String> {
+    match stack.pop() {
+        Some(val) => Ok(val),
+        None => Err("stack underflow".to_string())
+    }
+}
+
+/// The number of a Number or Boolean, just like `Element::value` does it.
+fn number(val: &Element) -> Option<i64> {
+    match *val {
+        Element::Number(n) => Some(n),
+        Element::Boolean(b) => Some(if b { 1 } else { 0 }),
+        _ => None
+    }
+}
+
+/// Run a program both on a `Machine` and compiled, checking they end up the same.
+#[cfg(test)]
+fn assert_agrees_with_machine(program: Box<Element>, env: HashMap<String, Box<Element>>) {
+    use {Machine, Outcome};
+
+    let mut m = Machine::new(program.clone(), env.clone());
+    assert_eq!(Outcome::Done, m.run());
+
+    let mut vm_env = env;
+    let result = compile(&program).unwrap().run(&mut vm_env);
+
+    assert_eq!(Ok(m.expression().clone()), result);
+    assert_eq!(m.clone_env(), vm_env);
+}
+
+#[test]
+fn test_compile_and_disassemble() {
+    let program = compile(&while_!(
+        less_than!(variable!("x"), number!(5)),
+        assign!("x", multiply!(variable!("x"), number!(3)))
+        )).unwrap();
+
+    assert_eq!(
+        "0000 load x\n\
+         0001 push 5\n\
+         0002 lt\n\
+         0003 jump-if-false 0011\n\
+         0004 load x\n\
+         0005 push 3\n\
+         0006 mul\n\
+         0007 store x\n\
+         0008 push do-nothing\n\
+         0009 pop\n\
+         0010 jump 0000\n\
+         0011 push do-nothing\n".to_string(),
+        program.disassemble());
+}
+
+#[test]
+fn test_vm_agrees_with_machine() {
+    // The programs of the machine tests above.
+    assert_agrees_with_machine(multiply!(add!(number!(3), number!(4)), number!(2)), HashMap::new());
+    assert_agrees_with_machine(less_than!(number!(2), number!(3)), HashMap::new());
+
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(3));
+    env.insert("y".to_string(), number!(4));
+    assert_agrees_with_machine(add!(variable!("x"), variable!("y")), env);
+
+    let mut env = HashMap::new();
+    env.insert("y".to_string(), number!(1));
+    assert_agrees_with_machine(
+        sequence!(
+            assign!("x", number!(3)),
+            assign!("res", add!(add!(number!(38), variable!("x")), variable!("y")))
+            ),
+        env);
+
+    assert_agrees_with_machine(
+        sequence!(
+            assign!("x", boolean!(false)),
+            ifelse!(variable!("x"), assign!("y", number!(1)), assign!("y", number!(42)))
+            ),
+        HashMap::new());
+
+    assert_agrees_with_machine(ifelse!(less_than!(number!(1), number!(2)), number!(1), number!(2)), HashMap::new());
+    assert_agrees_with_machine(if_!(boolean!(false), number!(1)), HashMap::new());
+
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+    assert_agrees_with_machine(
+        while_!(
+            less_than!(variable!("x"), number!(5)),
+            assign!("x", multiply!(variable!("x"), number!(3)))
+            ),
+        env);
+}
+
+#[test]
+fn test_vm_reports_errors() {
+    let mut env = HashMap::new();
+
+    let program = compile(&add!(number!(1), variable!("unset"))).unwrap();
+    assert_eq!(Err("type mismatch in Add at 0002".to_string()), program.run(&mut env));
+
+    let program = compile(&if_!(number!(1), number!(2))).unwrap();
+    assert_eq!(Err("type mismatch in JumpIfFalse(4) at 0001".to_string()), program.run(&mut env));
+
+    let program = compile(&multiply!(number!(::std::i64::MAX), number!(2))).unwrap();
+    assert_eq!(Err("overflow in Multiply at 0002".to_string()), program.run(&mut env));
+}
+
+#[test]
+fn test_compile_rejects_unsupported_elements() {
+    assert_eq!(Err("cannot compile print 1".to_string()), compile(&print_!(number!(1))));
+    assert!(compile(&sequence!(assign!("x", number!(1)), list!())).is_err());
+}
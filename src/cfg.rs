@@ -0,0 +1,294 @@
+//! Control-flow graphs of SIMPLE programs.
+//!
+//! A program is lowered into basic blocks: statements that always run one after another,
+//! followed by a jump to the next block, a branch on a condition or the end of the program.
+//! Sequences, `if`s and `while`s, with `break` and `continue` in them, become edges between
+//! blocks, everything else is a single statement. A `while` loop is a block branching on its
+//! condition, with a back edge from the end of its body. Blocks that are never reached, like the
+//! rest of a branch after `break`, are left out.
+//!
+//! `Graph::to_dot` renders the graph in the DOT language of Graphviz, with back edges dashed.
+
+use Element;
+
+/// How a basic block ends.
+#[derive(Clone,PartialEq,Debug)]
+pub enum Exit {
+    /// Continue with the given block.
+    Goto(usize),
+    /// Continue with the first block if the condition reduces to true, otherwise with the second.
+    Branch(Element, usize, usize),
+    /// Go back to the head of a loop, i.e. a back edge.
+    Loop(usize),
+    /// The program is done.
+    End
+}
+
+/// Statements that always run one after another.
+#[derive(Clone,PartialEq,Debug)]
+pub struct Block {
+    pub statements: Vec<Element>,
+    pub exit: Exit
+}
+
+/// A control-flow graph. The program starts in the first block.
+#[derive(Clone,PartialEq,Debug)]
+pub struct Graph {
+    pub blocks: Vec<Block>
+}
+
+/// Lower a program into a control-flow graph.
+pub fn build(element: &Element) -> Graph {
+    let mut builder = Builder { blocks: Vec::new(), loops: Vec::new() };
+    let entry = builder.block();
+    builder.lower(element, entry);
+    Graph { blocks: reachable(builder.blocks) }
+}
+
+/// The blocks reached from the first one, in their original order, with their exits renumbered.
+fn reachable(blocks: Vec<Block>) -> Vec<Block> {
+    let mut reached = vec![false; blocks.len()];
+    let mut pending = vec![0];
+    while let Some(i) = pending.pop() {
+        if reached[i] {
+            continue;
+        }
+        reached[i] = true;
+        match blocks[i].exit {
+            Exit::Goto(next) | Exit::Loop(next) => pending.push(next),
+            Exit::Branch(_, cons, alt) => {
+                pending.push(alt);
+                pending.push(cons);
+            },
+            Exit::End => {}
+        }
+    }
+
+    let mut numbers = Vec::new();
+    let mut next = 0;
+    for &r in reached.iter() {
+        numbers.push(next);
+        if r {
+            next += 1;
+        }
+    }
+
+    blocks.into_iter().zip(reached.into_iter()).filter(|&(_, r)| r).map(|(block, _)| {
+        let exit = match block.exit {
+            Exit::Goto(target) => Exit::Goto(numbers[target]),
+            Exit::Branch(cond, cons, alt) => Exit::Branch(cond, numbers[cons], numbers[alt]),
+            Exit::Loop(head) => Exit::Loop(numbers[head]),
+            Exit::End => Exit::End
+        };
+        Block { statements: block.statements, exit: exit }
+    }).collect()
+}
+
+struct Builder {
+    blocks: Vec<Block>,
+    /// The head and the exit of every loop around the element being lowered.
+    loops: Vec<(usize, usize)>
+}
+
+impl Builder {
+    /// Start a new, empty block.
+    fn block(&mut self) -> usize {
+        self.blocks.push(Block { statements: Vec::new(), exit: Exit::End });
+        self.blocks.len() - 1
+    }
+
+    /// Lower an element, continuing the current block. Returns the block the program continues
+    /// in afterwards.
+    fn lower(&mut self, element: &Element, current: usize) -> usize {
+        match *element {
+            Element::DoNothing => current,
+            Element::Sequence(ref first, ref second) => {
+                let current = self.lower(first, current);
+                self.lower(second, current)
+            },
+            Element::IfElse(ref cond, ref cons, ref alt) => {
+                let (cons_start, alt_start) = (self.block(), self.block());
+                self.blocks[current].exit = Exit::Branch(*cond.clone(), cons_start, alt_start);
+                let cons_end = self.lower(cons, cons_start);
+                let alt_end = self.lower(alt, alt_start);
+
+                let join = self.block();
+                self.blocks[cons_end].exit = Exit::Goto(join);
+                self.blocks[alt_end].exit = Exit::Goto(join);
+                join
+            },
+            Element::While(ref cond, ref body, ref invariant) => {
+                let head = self.block();
+                self.blocks[current].exit = Exit::Goto(head);
+                if let Some(ref invariant) = *invariant {
+                    let message = format!("loop invariant {:?}", invariant);
+                    self.blocks[head].statements.push(Element::Assert(invariant.clone(), message));
+                }
+
+                let (body_start, exit) = (self.block(), self.block());
+                self.blocks[head].exit = Exit::Branch(*cond.clone(), body_start, exit);
+                self.loops.push((head, exit));
+                let body_end = self.lower(body, body_start);
+                self.loops.pop();
+                self.blocks[body_end].exit = Exit::Loop(head);
+                exit
+            },
+            Element::Break | Element::Continue if !self.loops.is_empty() => {
+                let (head, exit) = *self.loops.last().unwrap();
+                self.blocks[current].exit = match *element {
+                    Element::Break => Exit::Goto(exit),
+                    _ => Exit::Loop(head)
+                };
+                // Whatever follows is never run.
+                self.block()
+            },
+            _ => {
+                self.blocks[current].statements.push(element.clone());
+                current
+            }
+        }
+    }
+}
+
+impl Graph {
+    /// The graph in the DOT language. Every block is a box listing its statements, followed by
+    /// the condition it branches on, if any.
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n".to_string();
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut label = format!("b{}\\l", i);
+            for statement in block.statements.iter() {
+                label.push_str(&escape(&format!("{:?}", statement)));
+                label.push_str("\\l");
+            }
+            match block.exit {
+                Exit::Branch(ref cond, _, _) => {
+                    label.push_str(&escape(&format!("if {:?}", cond)));
+                    label.push_str("\\l");
+                },
+                Exit::End => label.push_str("end\\l"),
+                _ => {}
+            }
+            dot.push_str(&format!("    b{} [label=\"{}\"];\n", i, label));
+        }
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            match block.exit {
+                Exit::Goto(target) => dot.push_str(&format!("    b{} -> b{};\n", i, target)),
+                Exit::Branch(_, cons, alt) => {
+                    dot.push_str(&format!("    b{} -> b{} [label=\"true\"];\n", i, cons));
+                    dot.push_str(&format!("    b{} -> b{} [label=\"false\"];\n", i, alt));
+                },
+                Exit::Loop(head) => dot.push_str(&format!("    b{} -> b{} [style=dashed];\n", i, head)),
+                Exit::End => {}
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Escape text for a quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
+#[test]
+fn test_build_straight_line() {
+    let graph = build(&sequence!(
+        assign!("x", number!(1)),
+        sequence!(print_!(variable!("x")), assign!("y", variable!("x")))
+        ));
+
+    assert_eq!(
+        vec![Block {
+            statements: vec![*assign!("x", number!(1)), *print_!(variable!("x")), *assign!("y", variable!("x"))],
+            exit: Exit::End
+        }],
+        graph.blocks);
+}
+
+#[test]
+fn test_build_branches_and_loops() {
+    let graph = build(&sequence!(
+        ifelse!(variable!("c"), assign!("x", number!(1)), assign!("x", number!(2))),
+        while_!(
+            less_than!(variable!("x"), number!(10)),
+            sequence!(
+                if_!(variable!("c"), break_!()),
+                assign!("x", add!(variable!("x"), number!(1)))
+                )
+            )
+        ));
+
+    let exits: Vec<Exit> = graph.blocks.iter().map(|b| b.exit.clone()).collect();
+    assert_eq!(
+        vec![
+            Exit::Branch(*variable!("c"), 1, 2),
+            Exit::Goto(3),
+            Exit::Goto(3),
+            Exit::Goto(4),
+            Exit::Branch(*less_than!(variable!("x"), number!(10)), 5, 6),
+            Exit::Branch(*variable!("c"), 7, 8),
+            Exit::End,
+            // `break`, without the unreachable rest of its branch.
+            Exit::Goto(6),
+            Exit::Goto(9),
+            Exit::Loop(4)
+        ],
+        exits);
+    assert_eq!(vec![*assign!("x", add!(variable!("x"), number!(1)))], graph.blocks[9].statements);
+}
+
+#[test]
+fn test_build_leaves_out_unreachable_blocks() {
+    //  while (c) [ x = 1; break ]
+    let graph = build(&while_!(variable!("c"), sequence!(assign!("x", number!(1)), break_!())));
+
+    assert_eq!(
+        vec![
+            Block { statements: vec![], exit: Exit::Goto(1) },
+            Block { statements: vec![], exit: Exit::Branch(*variable!("c"), 2, 3) },
+            Block { statements: vec![*assign!("x", number!(1))], exit: Exit::Goto(3) },
+            Block { statements: vec![], exit: Exit::End }
+        ],
+        graph.blocks);
+    assert_eq!(
+        "digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n    \
+         b0 [label=\"b0\\l\"];\n    \
+         b1 [label=\"b1\\lif c\\l\"];\n    \
+         b2 [label=\"b2\\lx = 1\\l\"];\n    \
+         b3 [label=\"b3\\lend\\l\"];\n    \
+         b0 -> b1;\n    \
+         b1 -> b2 [label=\"true\"];\n    \
+         b1 -> b3 [label=\"false\"];\n    \
+         b2 -> b3;\n\
+         }\n".to_string(),
+        graph.to_dot());
+}
+
+#[test]
+fn test_to_dot() {
+    let graph = build(&sequence!(
+        assign!("x", number!(0)),
+        while_!(
+            less_than!(variable!("x"), number!(3)),
+            sequence!(assert_!(boolean!(true), "\"quoted\""), assign!("x", add!(variable!("x"), number!(1))))
+            )
+        ));
+
+    assert_eq!(
+        "digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n    \
+         b0 [label=\"b0\\lx = 0\\l\"];\n    \
+         b1 [label=\"b1\\lif x < 3\\l\"];\n    \
+         b2 [label=\"b2\\lassert (true, \\\"\\\\\\\"quoted\\\\\\\"\\\")\\lx = x + 1\\l\"];\n    \
+         b3 [label=\"b3\\lend\\l\"];\n    \
+         b0 -> b1;\n    \
+         b1 -> b2 [label=\"true\"];\n    \
+         b1 -> b3 [label=\"false\"];\n    \
+         b2 -> b1 [style=dashed];\n\
+         }\n".to_string(),
+        graph.to_dot());
+}
@@ -0,0 +1,170 @@
+//! Exploration of all reduction paths of nondeterministic programs.
+//!
+//! Starting from a single configuration of expression and environment, all successors are
+//! followed breadth-first. Configurations already seen on the way are not explored again.
+
+use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
+
+use {Element, Fingerprint};
+
+/// The result of exploring all reduction paths of a program.
+pub struct Exploration {
+    /// The distinct irreducible configurations reachable from the start.
+    pub finals: Vec<(Element, HashMap<String, Box<Element>>)>,
+    /// The distinct reachable configurations that are not final but have no successors, like a
+    /// `read` without input.
+    pub stuck: Vec<(Element, HashMap<String, Box<Element>>)>,
+    /// Wether some paths were cut off by the depth limit.
+    pub truncated: bool
+}
+
+/// Follow every reduction path of `expression` for at most `max_depth` steps and collect the
+/// final and stuck configurations.
+pub fn explore(expression: &Element, environment: &HashMap<String, Box<Element>>, max_depth: usize) -> Exploration {
+    let mut seen = HashSet::new();
+    seen.insert(Fingerprint::new(expression, environment));
+    let mut frontier = vec![(expression.clone(), environment.clone())];
+    let mut exploration = Exploration { finals: Vec::new(), stuck: Vec::new(), truncated: false };
+    let mut depth = 0;
+
+    while !frontier.is_empty() {
+        let mut next = Vec::new();
+
+        for (expression, environment) in frontier.into_iter() {
+            if !expression.is_reducible() {
+                exploration.finals.push((expression, environment));
+                continue;
+            }
+            if depth == max_depth {
+                exploration.truncated = true;
+                continue;
+            }
+
+            let successors = expression.successors(&environment);
+            if successors.is_empty() {
+                exploration.stuck.push((expression, environment));
+                continue;
+            }
+            for (expression, environment) in successors.into_iter() {
+                if seen.insert(Fingerprint::new(&expression, &environment)) {
+                    next.push((expression, environment));
+                }
+            }
+        }
+
+        frontier = next;
+        depth += 1;
+    }
+
+    exploration
+}
+
+#[test]
+fn test_choose_has_two_successors() {
+    let env = HashMap::new();
+    let c = choose!(assign!("x", number!(1)), assign!("x", number!(2)));
+    assert_eq!("choose [ x = 1 ] or [ x = 2 ]".to_string(), format!("{:?}", c));
+
+    let successors = c.successors(&env);
+
+    assert_eq!(2, successors.len());
+    assert_eq!("x = 1".to_string(), format!("{:?}", successors[0].0));
+    assert_eq!("x = 2".to_string(), format!("{:?}", successors[1].0));
+    assert_eq!(0, successors[0].1.len());
+}
+
+#[test]
+fn test_deterministic_step_has_one_successor() {
+    let env = HashMap::new();
+    let a = add!(number!(1), number!(2));
+
+    let successors = a.successors(&env);
+
+    assert_eq!(1, successors.len());
+    assert_eq!(Element::Number(3), successors[0].0);
+    assert_eq!(0, number!(3).successors(&env).len());
+}
+
+#[test]
+fn test_explore_finds_all_final_states() {
+    let env = HashMap::new();
+
+    //  x = choose [ 1 ] or [ 2 ]; y = choose [ x ] or [ 10 ]
+    let program = sequence!(
+        assign!("x", choose!(number!(1), number!(2))),
+        assign!("y", choose!(variable!("x"), number!(10)))
+        );
+
+    let exploration = explore(&program, &env, 100);
+
+    assert_eq!(false, exploration.truncated);
+    assert_eq!(4, exploration.finals.len());
+    assert!(exploration.stuck.is_empty());
+
+    let mut ys: Vec<i64> = exploration.finals.iter()
+        .map(|&(_, ref env)| env.get(&"y".to_string()).unwrap().value())
+        .collect();
+    ys.sort();
+    assert_eq!(vec![1, 2, 10, 10], ys);
+}
+
+#[test]
+fn test_explore_deduplicates_configurations() {
+    let env = HashMap::new();
+    let program = assign!("x", choose!(number!(1), number!(1)));
+
+    let exploration = explore(&program, &env, 100);
+
+    assert_eq!(1, exploration.finals.len());
+    assert_eq!(1, exploration.finals[0].1.get(&"x".to_string()).unwrap().value());
+}
+
+#[test]
+fn test_explore_respects_depth_limit() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(0));
+
+    //  while (true) [ x = x + choose [ 0 ] or [ 1 ] ]
+    let program = while_!(
+        boolean!(true),
+        assign!("x", add!(variable!("x"), choose!(number!(0), number!(1))))
+        );
+
+    let exploration = explore(&program, &env, 50);
+
+    assert_eq!(true, exploration.truncated);
+    assert_eq!(0, exploration.finals.len());
+}
+
+#[test]
+fn test_explore_terminates_on_finite_state_loops() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(0));
+
+    //  while (true) [ x = choose [ 0 ] or [ 1 ] ]
+    let program = while_!(boolean!(true), assign!("x", choose!(number!(0), number!(1))));
+
+    let exploration = explore(&program, &env, 1000);
+
+    assert_eq!(false, exploration.truncated);
+    assert_eq!(0, exploration.finals.len());
+}
+
+#[test]
+fn test_explore_reports_stuck_configurations() {
+    let env = HashMap::new();
+
+    //  x = choose [ 1 ] or [ 2 ]; read y
+    let program = sequence!(assign!("x", choose!(number!(1), number!(2))), read!("y"));
+
+    let exploration = explore(&program, &env, 100);
+
+    assert_eq!(false, exploration.truncated);
+    assert_eq!(0, exploration.finals.len());
+    assert_eq!(2, exploration.stuck.len());
+    for &(ref expression, ref env) in exploration.stuck.iter() {
+        assert_eq!(&*read!("y"), expression);
+        assert!(env.contains_key("x"));
+    }
+}
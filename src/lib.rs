@@ -34,9 +34,12 @@ use std::fmt::Debug;
 use std::fmt::Formatter;
 use std::fmt::Result;
 use std::collections::hash_map::HashMap;
+use std::collections::{BTreeMap, VecDeque};
+
+use schedule::Scheduler;
 
 /// Our AST elements.
-#[derive(Clone,PartialEq)]
+#[derive(Clone,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub enum Element {
     /// A simple number object, this cannot be reduced further.
     Number(i64),
@@ -60,7 +63,62 @@ pub enum Element {
     /// If `condition` reduces to true, the `consequence` is used furhter, otherwise the `alternative`
     IfElse(Box<Element>, Box<Element>, Box<Element>),
     /// A while loop. Runs until the `condition` reduces to false.
-    While(Box<Element>, Box<Element>),
+    /// The optional invariant is asserted every time the loop is unfolded.
+    While(Box<Element>, Box<Element>, Option<Box<Element>>),
+    /// A for loop: `init` runs once, then `body` followed by `update` while `condition`
+    /// reduces to true. A `continue` in the body still runs `update`.
+    For(Box<Element>, Box<Element>, Box<Element>, Box<Element>),
+    /// A do-while loop. Runs the body once, then until the `condition` reduces to false.
+    DoWhile(Box<Element>, Box<Element>),
+    /// One iteration of a loop: the body in progress followed by the loop itself.
+    /// This is the frame `break` and `continue` jump out of. Displayed just like a sequence.
+    Iteration(Box<Element>, Box<Element>),
+    /// Leaves the innermost loop.
+    Break,
+    /// Skips the rest of the body of the innermost loop.
+    Continue,
+    /// A block with a local variable. `init` is reduced and bound to `name` while `body` runs,
+    /// shadowing any outer binding of the same name.
+    Let(String, Box<Element>, Box<Element>),
+    /// A block in progress. Keeps the outer binding of the local variable (if any), which is put
+    /// back once the body is completely reduced.
+    Scope(String, Option<Box<Element>>, Box<Element>),
+    /// A list of elements. Items are reduced from left to right, a list of irreducible items
+    /// cannot be reduced further.
+    List(Vec<Box<Element>>),
+    /// Indexing into a list. Both the list and the index are reduced first.
+    Index(Box<Element>, Box<Element>),
+    /// An assignment to a single item of the list stored in a variable.
+    AssignIndex(String, Box<Element>, Box<Element>),
+    /// The number of items in a list.
+    Length(Box<Element>),
+    /// A new list with the second element added at the end of the first.
+    Append(Box<Element>, Box<Element>),
+    /// A record of named fields. Fields are reduced from left to right, a record of irreducible
+    /// fields cannot be reduced further.
+    Record(Vec<(String, Box<Element>)>),
+    /// Reading a field of a record.
+    Field(Box<Element>, String),
+    /// An assignment to a single field of the record stored in a variable.
+    AssignField(String, String, Box<Element>),
+    /// Prints the value of an element to the output of the machine.
+    Print(Box<Element>),
+    /// Assigns the next value of the input of the machine to a variable.
+    Read(String),
+    /// Checks that the condition reduces to true, stopping the machine with the message otherwise.
+    Assert(Box<Element>, String),
+    /// Throws a value, aborting every element around it up to the closest `TryCatch`.
+    Throw(Box<Element>),
+    /// Reduces the body. If it throws, the thrown value is bound to the name while the handler
+    /// runs.
+    TryCatch(Box<Element>, String, Box<Element>),
+    /// A nondeterministic choice between two elements. Reduces to either of them.
+    Choose(Box<Element>, Box<Element>),
+    /// Runs two elements side by side. Each step reduces one of them, a scheduler decides which.
+    Parallel(Box<Element>, Box<Element>),
+    /// Describes a failed built-in operation, e.g. an index out of bounds. Failing operations
+    /// throw it. This cannot be reduced further.
+    Error(String),
     /// A simple no-op statement.
     DoNothing
 }
@@ -118,10 +176,127 @@ macro_rules! if_(
 );
 macro_rules! while_(
     ($condition:expr, $body:expr) => (
-        box Element::While($condition, $body)
+        box Element::While($condition, $body, None)
+    )
+);
+macro_rules! while_invariant(
+    ($condition:expr, $invariant:expr, $body:expr) => (
+        box Element::While($condition, $body, Some($invariant))
+    )
+);
+macro_rules! list(
+    ($($item:expr),*) => (
+        box Element::List(vec![$($item),*])
+    )
+);
+macro_rules! index(
+    ($list:expr, $index:expr) => (
+        box Element::Index($list, $index)
+    )
+);
+macro_rules! assign_index(
+    ($name:expr, $index:expr, $exp:expr) => (
+        box Element::AssignIndex($name.to_string(), $index, $exp)
+    )
+);
+macro_rules! length(
+    ($list:expr) => (
+        box Element::Length($list)
+    )
+);
+macro_rules! append(
+    ($list:expr, $item:expr) => (
+        box Element::Append($list, $item)
+    )
+);
+macro_rules! record(
+    ($($name:expr => $val:expr),*) => (
+        box Element::Record(vec![$(($name.to_string(), $val)),*])
+    )
+);
+macro_rules! field(
+    ($record:expr, $name:expr) => (
+        box Element::Field($record, $name.to_string())
+    )
+);
+macro_rules! assign_field(
+    ($record:expr, $name:expr, $exp:expr) => (
+        box Element::AssignField($record.to_string(), $name.to_string(), $exp)
+    )
+);
+macro_rules! for_(
+    ($init:expr, $condition:expr, $update:expr, $body:expr) => (
+        box Element::For($init, $condition, $update, $body)
+    )
+);
+macro_rules! do_while(
+    ($body:expr, $condition:expr) => (
+        box Element::DoWhile($body, $condition)
+    )
+);
+macro_rules! break_(
+    () => (
+        box Element::Break
+    )
+);
+macro_rules! continue_(
+    () => (
+        box Element::Continue
+    )
+);
+macro_rules! print_(
+    ($exp:expr) => (
+        box Element::Print($exp)
+    )
+);
+macro_rules! read(
+    ($name:expr) => (
+        box Element::Read($name.to_string())
+    )
+);
+macro_rules! assert_(
+    ($condition:expr, $message:expr) => (
+        box Element::Assert($condition, $message.to_string())
+    )
+);
+macro_rules! throw(
+    ($exp:expr) => (
+        box Element::Throw($exp)
+    )
+);
+macro_rules! try_catch(
+    ($body:expr, $name:expr, $handler:expr) => (
+        box Element::TryCatch($body, $name.to_string(), $handler)
+    )
+);
+macro_rules! let_(
+    ($name:expr, $init:expr, $body:expr) => (
+        box Element::Let($name.to_string(), $init, $body)
+    )
+);
+macro_rules! choose(
+    ($left:expr, $right:expr) => (
+        box Element::Choose($left, $right)
+    )
+);
+
+macro_rules! parallel(
+    ($left:expr, $right:expr) => (
+        box Element::Parallel($left, $right)
     )
 );
 
+pub mod explore;
+pub mod schedule;
+pub mod model_check;
+pub mod arena;
+pub mod zipper;
+pub mod bytecode;
+pub mod register;
+pub mod optimize;
+pub mod analysis;
+pub mod uninitialized;
+pub mod cfg;
 
 impl Debug for Element {
     /// Output a user-readable representation of the expression
@@ -138,14 +313,127 @@ impl Debug for Element {
             Element::IfElse(ref cond, ref cons, ref alt) => {
                 write!(f, "if ({:?}) [ {:?} ] else [ {:?} ]", cond, cons, alt)
             }
-            Element::While(ref cond, ref body) => {
+            Element::While(ref cond, ref body, None) => {
                 write!(f, "while ({:?}) [ {:?} ]", cond, body)
             }
+            Element::While(ref cond, ref body, Some(ref invariant)) => {
+                write!(f, "while ({:?}) invariant ({:?}) [ {:?} ]", cond, invariant, body)
+            }
+            Element::For(ref init, ref cond, ref update, ref body) => {
+                write!(f, "for ({:?}; {:?}; {:?}) [ {:?} ]", init, cond, update, body)
+            }
+            Element::DoWhile(ref body, ref cond) => {
+                write!(f, "do [ {:?} ] while ({:?})", body, cond)
+            }
+            Element::Iteration(ref body, ref whole) => write!(f, "{:?}; {:?}", body, whole),
+            Element::Break => write!(f, "break"),
+            Element::Continue => write!(f, "continue"),
+            Element::Let(ref name, ref init, ref body) => {
+                write!(f, "let {} = {:?} in [ {:?} ]", name, init, body)
+            }
+            Element::Scope(ref name, Some(ref outer), ref body) => {
+                write!(f, "local {} [ {:?} ] restores {:?}", name, body, outer)
+            }
+            Element::Scope(ref name, None, ref body) => {
+                write!(f, "local {} [ {:?} ] restores nothing", name, body)
+            }
+            Element::List(ref items) => {
+                try!(write!(f, "["));
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ", "));
+                    }
+                    try!(write!(f, "{:?}", item));
+                }
+                write!(f, "]")
+            }
+            Element::Index(ref list, ref index) => write!(f, "{:?}[{:?}]", list, index),
+            Element::AssignIndex(ref name, ref index, ref val) => {
+                write!(f, "{}[{:?}] = {:?}", name, index, val)
+            }
+            Element::Length(ref list) => write!(f, "length({:?})", list),
+            Element::Append(ref list, ref item) => write!(f, "append({:?}, {:?})", list, item),
+            Element::Record(ref fields) => {
+                try!(write!(f, "{{"));
+                for (i, &(ref name, ref val)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ","));
+                    }
+                    try!(write!(f, " {}: {:?}", name, val));
+                }
+                write!(f, " }}")
+            }
+            Element::Field(ref record, ref name) => write!(f, "{:?}.{}", record, name),
+            Element::AssignField(ref record, ref name, ref val) => {
+                write!(f, "{}.{} = {:?}", record, name, val)
+            }
+            Element::Print(ref val) => write!(f, "print {:?}", val),
+            Element::Read(ref name) => write!(f, "read {}", name),
+            Element::Assert(ref cond, ref message) => write!(f, "assert ({:?}, {:?})", cond, message),
+            Element::Throw(ref val) => write!(f, "throw {:?}", val),
+            Element::TryCatch(ref body, ref name, ref handler) => {
+                write!(f, "try [ {:?} ] catch ({}) [ {:?} ]", body, name, handler)
+            }
+            Element::Choose(ref left, ref right) => {
+                write!(f, "choose [ {:?} ] or [ {:?} ]", left, right)
+            }
+            Element::Parallel(ref left, ref right) => {
+                write!(f, "[ {:?} ] || [ {:?} ]", left, right)
+            }
+            Element::Error(ref message) => write!(f, "error({:?})", message),
             Element::DoNothing => write!(f, "do-nothing")
         }
     }
 }
 
+/// Everything outside of the environment a reduction step can have an effect on.
+pub struct Context {
+    /// All values printed so far, in order.
+    pub output: Vec<Box<Element>>,
+    /// Values not yet read by the program, in order.
+    pub input: VecDeque<Box<Element>>,
+    /// Set when a step could not make progress because the input ran out.
+    pub waiting_for_input: bool,
+    /// The message of an assertion that failed during a step.
+    pub failed_assertion: Option<String>,
+    /// Decisions to take at the next nondeterministic choices, `false` is left and `true` is
+    /// right. Once these run out, the scheduler decides.
+    pub choices: VecDeque<bool>,
+    /// Decides once the choices run out. Without a scheduler the left side is taken.
+    pub scheduler: Option<Box<Scheduler>>,
+    /// All nondeterministic decisions taken so far, in order.
+    pub decisions: Vec<bool>
+}
+
+impl Context {
+    /// Create a new context with no input and no output
+    pub fn new() -> Context {
+        Context {
+            output: Vec::new(),
+            input: VecDeque::new(),
+            waiting_for_input: false,
+            failed_assertion: None,
+            choices: VecDeque::new(),
+            scheduler: None,
+            decisions: Vec::new()
+        }
+    }
+
+    /// Take a nondeterministic decision between two elements, `false` for left and `true` for
+    /// right.
+    pub fn choose(&mut self, left: &Element, right: &Element) -> bool {
+        let decision = match self.choices.pop_front() {
+            Some(decision) => decision,
+            None => match self.scheduler {
+                Some(ref mut scheduler) => scheduler.schedule(left, right),
+                None => false
+            }
+        };
+        self.decisions.push(decision);
+        decision
+    }
+}
+
 impl Element {
     /// Wether or not an expression is reducible. See Element for more info.
     pub fn is_reducible(&self) -> bool {
@@ -160,7 +448,213 @@ impl Element {
             Element::Assign(_, _) => true,
             Element::Sequence(_, _) => true,
             Element::IfElse(_, _, _) => true,
-            Element::While(_, _) => true,
+            Element::While(_, _, _) => true,
+            Element::For(_, _, _, _) => true,
+            Element::DoWhile(_, _) => true,
+            Element::Iteration(_, _) => true,
+            Element::Break => false,
+            Element::Continue => false,
+            Element::Let(_, _, _) => true,
+            Element::Scope(_, _, _) => true,
+            Element::List(ref items) => items.iter().any(|i| i.is_reducible() || i.is_abrupt()),
+            Element::Index(_, _) => true,
+            Element::AssignIndex(_, _, _) => true,
+            Element::Length(_) => true,
+            Element::Append(_, _) => true,
+            Element::Record(ref fields) => {
+                fields.iter().any(|&(_, ref v)| v.is_reducible() || v.is_abrupt())
+            },
+            Element::Field(_, _) => true,
+            Element::AssignField(_, _, _) => true,
+            Element::Print(_) => true,
+            Element::Read(_) => true,
+            Element::Assert(_, _) => true,
+            Element::Throw(ref val) => val.is_reducible() || val.is_abrupt(),
+            Element::TryCatch(_, _, _) => true,
+            Element::Choose(_, _) => true,
+            Element::Parallel(_, _) => true,
+            Element::Error(_) => false,
+        }
+    }
+
+    /// Wether or not this describes a failed built-in operation.
+    pub fn is_error(&self) -> bool {
+        match *self {
+            Element::Error(_) => true,
+            _ => false
+        }
+    }
+
+    /// Wether or not this aborts the elements around it: a thrown value, `break` or `continue`.
+    pub fn is_abrupt(&self) -> bool {
+        match *self {
+            Element::Throw(_) => !self.is_reducible(),
+            Element::Break | Element::Continue => true,
+            _ => false
+        }
+    }
+
+    /// The number of a Number or Boolean, like `value`, but `None` for other elements.
+    fn number(&self) -> Option<i64> {
+        match *self {
+            Element::Number(_) | Element::Boolean(_) => Some(self.value()),
+            _ => None
+        }
+    }
+
+    /// The parts of an element that are reduced before the element itself, in the order they
+    /// are reduced in.
+    fn operands(&self) -> Vec<&Element> {
+        match *self {
+            Element::Add(ref l, ref r) => vec![&**l, &**r],
+            Element::Multiply(ref l, ref r) => vec![&**l, &**r],
+            Element::LessThan(ref l, ref r) => vec![&**l, &**r],
+            Element::Assign(_, ref expression) => vec![&**expression],
+            Element::Sequence(ref first, _) => vec![&**first],
+            Element::IfElse(ref cond, _, _) => vec![&**cond],
+            Element::For(ref init, _, _, _) => vec![&**init],
+            Element::Let(_, ref init, _) => vec![&**init],
+            Element::List(ref items) => items.iter().map(|i| &**i).collect(),
+            Element::Index(ref list, ref index) => vec![&**list, &**index],
+            Element::AssignIndex(_, ref index, ref val) => vec![&**index, &**val],
+            Element::Length(ref list) => vec![&**list],
+            Element::Append(ref list, ref item) => vec![&**list, &**item],
+            Element::Record(ref fields) => fields.iter().map(|&(_, ref v)| &**v).collect(),
+            Element::Field(ref record, _) => vec![&**record],
+            Element::AssignField(_, _, ref val) => vec![&**val],
+            Element::Print(ref val) => vec![&**val],
+            Element::Assert(ref cond, _) => vec![&**cond],
+            Element::Throw(ref val) => vec![&**val],
+            _ => vec![]
+        }
+    }
+
+    /// All elements directly contained in an element, in the order they appear in.
+    fn children(&self) -> Vec<&Element> {
+        match *self {
+            Element::Add(ref l, ref r) | Element::Multiply(ref l, ref r) |
+            Element::LessThan(ref l, ref r) | Element::Sequence(ref l, ref r) |
+            Element::DoWhile(ref l, ref r) | Element::Iteration(ref l, ref r) |
+            Element::Index(ref l, ref r) | Element::Append(ref l, ref r) |
+            Element::Choose(ref l, ref r) | Element::Parallel(ref l, ref r) => vec![&**l, &**r],
+            Element::IfElse(ref cond, ref cons, ref alt) => vec![&**cond, &**cons, &**alt],
+            Element::While(ref cond, ref body, ref invariant) => {
+                let mut children = vec![&**cond, &**body];
+                children.extend(invariant.iter().map(|i| &**i));
+                children
+            },
+            Element::For(ref init, ref cond, ref update, ref body) => vec![&**init, &**cond, &**update, &**body],
+            Element::Let(_, ref init, ref body) => vec![&**init, &**body],
+            Element::Scope(_, ref outer, ref body) => {
+                let mut children: Vec<&Element> = outer.iter().map(|o| &**o).collect();
+                children.push(&**body);
+                children
+            },
+            Element::AssignIndex(_, ref index, ref val) => vec![&**index, &**val],
+            Element::TryCatch(ref body, _, ref handler) => vec![&**body, &**handler],
+            Element::Assign(_, ref e) | Element::Length(ref e) | Element::Field(ref e, _) |
+            Element::AssignField(_, _, ref e) | Element::Print(ref e) | Element::Assert(ref e, _) |
+            Element::Throw(ref e) => vec![&**e],
+            Element::List(ref items) => items.iter().map(|i| &**i).collect(),
+            Element::Record(ref fields) => fields.iter().map(|&(_, ref v)| &**v).collect(),
+            _ => vec![]
+        }
+    }
+
+    /// Add the name of every variable the element assigns or binds to the list, once for every
+    /// time it does.
+    fn written(&self, variables: &mut Vec<String>) {
+        match *self {
+            Element::Assign(ref name, _) | Element::Read(ref name) | Element::AssignIndex(ref name, _, _) |
+            Element::AssignField(ref name, _, _) | Element::Let(ref name, _, _) |
+            Element::Scope(ref name, _, _) | Element::TryCatch(_, ref name, _) => variables.push(name.clone()),
+            _ => {}
+        }
+        for child in self.children() {
+            child.written(variables);
+        }
+    }
+
+    /// Wether the element contains a `break` or `continue`.
+    fn may_jump(&self) -> bool {
+        match *self {
+            Element::Break | Element::Continue => true,
+            _ => self.children().into_iter().any(|c| c.may_jump())
+        }
+    }
+
+    /// The names of the children of an element, in the order of `children`: the parts of the
+    /// element, `left`, `condition`, `body` and so on, the index of list items and the name of
+    /// record fields.
+    fn labels(&self) -> Vec<String> {
+        let names: Vec<&str> = match *self {
+            Element::Add(..) | Element::Multiply(..) | Element::LessThan(..) |
+            Element::Choose(..) | Element::Parallel(..) => vec!["left", "right"],
+            Element::Sequence(..) => vec!["first", "second"],
+            Element::IfElse(..) => vec!["condition", "consequence", "alternative"],
+            Element::While(..) => vec!["condition", "body", "invariant"],
+            Element::For(..) => vec!["init", "condition", "update", "body"],
+            Element::DoWhile(..) => vec!["body", "condition"],
+            Element::Iteration(..) => vec!["body", "loop"],
+            Element::Let(..) => vec!["init", "body"],
+            Element::Scope(_, Some(_), _) => vec!["outer", "body"],
+            Element::Scope(_, None, _) => vec!["body"],
+            Element::Index(..) => vec!["list", "index"],
+            Element::AssignIndex(..) => vec!["index", "value"],
+            Element::Length(..) => vec!["list"],
+            Element::Append(..) => vec!["list", "item"],
+            Element::Field(..) => vec!["record"],
+            Element::Assign(..) | Element::AssignField(..) | Element::Print(..) | Element::Throw(..) => vec!["value"],
+            Element::Assert(..) => vec!["condition"],
+            Element::TryCatch(..) => vec!["body", "handler"],
+            Element::List(ref items) => return (0..items.len()).map(|i| i.to_string()).collect(),
+            Element::Record(ref fields) => return fields.iter().map(|&(ref name, _)| name.clone()).collect(),
+            _ => vec![]
+        };
+        names.into_iter().map(|n| n.to_string()).collect()
+    }
+
+    /// The element with every child replaced by the result of `f` on it, called in the order of
+    /// `children`.
+    fn map_children<F>(&self, mut f: F) -> Element where F: FnMut(&Element) -> Element {
+        let mut c = |e: &Box<Element>| box f(e);
+
+        match *self {
+            Element::Add(ref l, ref r) => Element::Add(c(l), c(r)),
+            Element::Multiply(ref l, ref r) => Element::Multiply(c(l), c(r)),
+            Element::LessThan(ref l, ref r) => Element::LessThan(c(l), c(r)),
+            Element::Assign(ref name, ref val) => Element::Assign(name.clone(), c(val)),
+            Element::Sequence(ref first, ref second) => Element::Sequence(c(first), c(second)),
+            Element::IfElse(ref cond, ref cons, ref alt) => Element::IfElse(c(cond), c(cons), c(alt)),
+            Element::While(ref cond, ref body, ref invariant) => {
+                let (cond, body) = (c(cond), c(body));
+                Element::While(cond, body, invariant.as_ref().map(|i| c(i)))
+            },
+            Element::For(ref init, ref cond, ref update, ref body) => Element::For(c(init), c(cond), c(update), c(body)),
+            Element::DoWhile(ref body, ref cond) => Element::DoWhile(c(body), c(cond)),
+            Element::Iteration(ref body, ref whole) => Element::Iteration(c(body), c(whole)),
+            Element::Let(ref name, ref init, ref body) => Element::Let(name.clone(), c(init), c(body)),
+            Element::Scope(ref name, ref outer, ref body) => {
+                let outer = outer.as_ref().map(|o| c(o));
+                Element::Scope(name.clone(), outer, c(body))
+            },
+            Element::List(ref items) => Element::List(items.iter().map(|i| c(i)).collect()),
+            Element::Index(ref list, ref index) => Element::Index(c(list), c(index)),
+            Element::AssignIndex(ref name, ref index, ref val) => Element::AssignIndex(name.clone(), c(index), c(val)),
+            Element::Length(ref list) => Element::Length(c(list)),
+            Element::Append(ref list, ref item) => Element::Append(c(list), c(item)),
+            Element::Record(ref fields) => {
+                Element::Record(fields.iter().map(|&(ref name, ref val)| (name.clone(), c(val))).collect())
+            },
+            Element::Field(ref record, ref name) => Element::Field(c(record), name.clone()),
+            Element::AssignField(ref record, ref name, ref val) => Element::AssignField(record.clone(), name.clone(), c(val)),
+            Element::Print(ref val) => Element::Print(c(val)),
+            Element::Assert(ref cond, ref message) => Element::Assert(c(cond), message.clone()),
+            Element::Throw(ref val) => Element::Throw(c(val)),
+            Element::TryCatch(ref body, ref name, ref handler) => Element::TryCatch(c(body), name.clone(), c(handler)),
+            Element::Choose(ref l, ref r) => Element::Choose(c(l), c(r)),
+            Element::Parallel(ref l, ref r) => Element::Parallel(c(l), c(r)),
+            _ => self.clone()
         }
     }
 
@@ -178,32 +672,88 @@ impl Element {
 
     /// Reduce the expression according to the rules for the current element.
     pub fn reduce(&self, environment: &mut HashMap<String, Box<Element>>) -> Element {
+        self.reduce_in(environment, &mut Context::new())
+    }
+
+    /// All possible results of reducing the expression by one step, one for every combination of
+    /// nondeterministic decisions. Programs waiting for input have no successors.
+    pub fn successors(&self, environment: &HashMap<String, Box<Element>>) -> Vec<(Element, HashMap<String, Box<Element>>)> {
+        let mut successors = Vec::new();
+        if !self.is_reducible() {
+            return successors;
+        }
+
+        let mut pending = vec![Vec::new()];
+        while let Some(choices) = pending.pop() {
+            let mut context = Context::new();
+            context.choices = choices.iter().cloned().collect();
+            let mut environment = environment.clone();
+            let next = self.reduce_in(&mut environment, &mut context);
+
+            // Every decision that went left by default could have gone right as well.
+            for i in choices.len()..context.decisions.len() {
+                let mut alternative = context.decisions[..i].to_vec();
+                alternative.push(true);
+                pending.push(alternative);
+            }
+
+            if !context.waiting_for_input {
+                successors.push((next, environment));
+            }
+        }
+
+        successors
+    }
+
+    /// Reduce the expression like `reduce`, recording any effects of the step in `context`.
+    pub fn reduce_in(&self, environment: &mut HashMap<String, Box<Element>>, context: &mut Context) -> Element {
+        // A thrown value, `break` or `continue` in the next operand to reduce aborts the whole element.
+        match self.operands().into_iter().find(|o| o.is_reducible() || o.is_abrupt()) {
+            Some(abrupt) if abrupt.is_abrupt() => return abrupt.clone(),
+            _ => {}
+        }
+
         match *self {
             Element::Add(ref l, ref r) => {
                 if l.is_reducible() {
-                    Element::Add(box l.reduce(environment), r.clone())
+                    Element::Add(box l.reduce_in(environment, context), r.clone())
                 } else if r.is_reducible() {
-                    Element::Add(l.clone(), box r.reduce(environment))
+                    Element::Add(l.clone(), box r.reduce_in(environment, context))
                 } else {
-                    Element::Number(l.value() + r.value())
+                    match (l.number(), r.number()) {
+                        (Some(l), Some(r)) => match l.checked_add(r) {
+                            Some(sum) => Element::Number(sum),
+                            None => overflow(self)
+                        },
+                        _ => type_mismatch(self)
+                    }
                 }
             },
             Element::Multiply(ref l, ref r) => {
                 if l.is_reducible() {
-                    Element::Multiply(box l.reduce(environment), r.clone())
+                    Element::Multiply(box l.reduce_in(environment, context), r.clone())
                 } else if r.is_reducible() {
-                    Element::Multiply(l.clone(), box r.reduce(environment))
+                    Element::Multiply(l.clone(), box r.reduce_in(environment, context))
                 } else {
-                    Element::Number(l.value() * r.value())
+                    match (l.number(), r.number()) {
+                        (Some(l), Some(r)) => match l.checked_mul(r) {
+                            Some(product) => Element::Number(product),
+                            None => overflow(self)
+                        },
+                        _ => type_mismatch(self)
+                    }
                 }
             },
             Element::LessThan(ref l, ref r) => {
                 if l.is_reducible() {
-                    Element::LessThan(box l.reduce(environment), r.clone())
+                    Element::LessThan(box l.reduce_in(environment, context), r.clone())
                 } else if r.is_reducible() {
-                    Element::LessThan(l.clone(), box r.reduce(environment))
+                    Element::LessThan(l.clone(), box r.reduce_in(environment, context))
                 } else {
-                    Element::Boolean(l.value() < r.value())
+                    match (l.number(), r.number()) {
+                        (Some(l), Some(r)) => Element::Boolean(l < r),
+                        _ => type_mismatch(self)
+                    }
                 }
             },
             Element::Variable(ref v) => {
@@ -216,7 +766,7 @@ impl Element {
             },
             Element::Assign(ref name, ref expression) => {
                 if expression.is_reducible() {
-                    Element::Assign(name.clone(), box expression.reduce(environment))
+                    Element::Assign(name.clone(), box expression.reduce_in(environment, context))
                 } else {
                     environment.insert(name.clone(), expression.clone());
                     Element::DoNothing
@@ -226,7 +776,7 @@ impl Element {
                 *second.clone()
             },
             Element::Sequence(ref first, ref second) => {
-                Element::Sequence(box first.reduce(environment), second.clone())
+                Element::Sequence(box first.reduce_in(environment, context), second.clone())
             },
             Element::IfElse(box Element::Boolean(true), ref cons, _) => {
                 *cons.clone()
@@ -236,16 +786,248 @@ impl Element {
             },
             Element::IfElse(ref cond, ref cons, ref alt) => {
                 if cond.is_reducible() {
-                    Element::IfElse(box cond.reduce(environment), cons.clone(), alt.clone())
+                    Element::IfElse(box cond.reduce_in(environment, context), cons.clone(), alt.clone())
                 } else {
-                    panic!("Condition in if not reducible (but not bool): {:?}", cond)
+                    type_mismatch(self)
                 }
             },
-            Element::While(ref cond, ref body) => {
-                Element::IfElse(cond.clone(), box Element::Sequence(body.clone(), box self.clone()), box Element::DoNothing)
+            Element::While(ref cond, ref body, ref invariant) => {
+                let unfolded = Element::IfElse(cond.clone(), box Element::Iteration(body.clone(), box self.clone()), box Element::DoNothing);
+                match *invariant {
+                    Some(ref invariant) => {
+                        let message = format!("loop invariant {:?}", invariant);
+                        Element::Sequence(box Element::Assert(invariant.clone(), message), box unfolded)
+                    },
+                    None => unfolded
+                }
+            }
+            Element::For(box Element::DoNothing, ref cond, ref update, ref body) => {
+                Element::IfElse(
+                    cond.clone(),
+                    box Element::Iteration(body.clone(), box Element::Sequence(update.clone(), box self.clone())),
+                    box Element::DoNothing)
+            }
+            Element::For(ref init, ref cond, ref update, ref body) => {
+                Element::For(box init.reduce_in(environment, context), cond.clone(), update.clone(), body.clone())
+            }
+            Element::DoWhile(ref body, ref cond) => {
+                Element::Iteration(body.clone(), box Element::While(cond.clone(), body.clone(), None))
             }
+            Element::Iteration(ref body, ref whole) => {
+                if body.is_reducible() {
+                    Element::Iteration(box body.reduce_in(environment, context), whole.clone())
+                } else {
+                    match **body {
+                        Element::Break => Element::DoNothing,
+                        Element::Throw(_) => *body.clone(),
+                        _ => *whole.clone()
+                    }
+                }
+            }
+            Element::Let(ref name, ref init, ref body) => {
+                if init.is_reducible() {
+                    Element::Let(name.clone(), box init.reduce_in(environment, context), body.clone())
+                } else {
+                    let outer = environment.insert(name.clone(), init.clone());
+                    Element::Scope(name.clone(), outer, body.clone())
+                }
+            },
+            Element::Scope(ref name, ref outer, ref body) => {
+                if body.is_reducible() {
+                    Element::Scope(name.clone(), outer.clone(), box body.reduce_in(environment, context))
+                } else {
+                    match *outer {
+                        Some(ref value) => { environment.insert(name.clone(), value.clone()); },
+                        None => { environment.remove(name); }
+                    }
+                    *body.clone()
+                }
+            },
+            Element::List(ref items) => {
+                let mut items = items.clone();
+                match items.iter_mut().find(|i| i.is_reducible()) {
+                    Some(item) => *item = box item.reduce_in(environment, context),
+                    None => {}
+                }
+                Element::List(items)
+            },
+            Element::Index(ref list, ref index) => {
+                if list.is_reducible() {
+                    Element::Index(box list.reduce_in(environment, context), index.clone())
+                } else if index.is_reducible() {
+                    Element::Index(list.clone(), box index.reduce_in(environment, context))
+                } else {
+                    match (&**list, &**index) {
+                        (&Element::List(ref items), &Element::Number(index)) => {
+                            match checked_index(items.len(), index) {
+                                Some(i) => *items[i].clone(),
+                                None => out_of_bounds(items.len(), index)
+                            }
+                        },
+                        _ => type_mismatch(self)
+                    }
+                }
+            },
+            Element::AssignIndex(ref name, ref index, ref val) => {
+                if index.is_reducible() {
+                    Element::AssignIndex(name.clone(), box index.reduce_in(environment, context), val.clone())
+                } else if val.is_reducible() {
+                    Element::AssignIndex(name.clone(), index.clone(), box val.reduce_in(environment, context))
+                } else {
+                    let (mut items, index) = match (environment.get(name), &**index) {
+                        (Some(&box Element::List(ref items)), &Element::Number(index)) => (items.clone(), index),
+                        _ => return type_mismatch(self)
+                    };
+                    match checked_index(items.len(), index) {
+                        Some(i) => {
+                            items[i] = val.clone();
+                            environment.insert(name.clone(), box Element::List(items));
+                            Element::DoNothing
+                        },
+                        None => out_of_bounds(items.len(), index)
+                    }
+                }
+            },
+            Element::Length(ref list) => {
+                if list.is_reducible() {
+                    Element::Length(box list.reduce_in(environment, context))
+                } else {
+                    match **list {
+                        Element::List(ref items) => Element::Number(items.len() as i64),
+                        _ => type_mismatch(self)
+                    }
+                }
+            },
+            Element::Append(ref list, ref item) => {
+                if list.is_reducible() {
+                    Element::Append(box list.reduce_in(environment, context), item.clone())
+                } else if item.is_reducible() {
+                    Element::Append(list.clone(), box item.reduce_in(environment, context))
+                } else {
+                    match **list {
+                        Element::List(ref items) => {
+                            let mut items = items.clone();
+                            items.push(item.clone());
+                            Element::List(items)
+                        },
+                        _ => type_mismatch(self)
+                    }
+                }
+            },
+            Element::Record(ref fields) => {
+                let mut fields = fields.clone();
+                match fields.iter_mut().find(|&&mut (_, ref v)| v.is_reducible()) {
+                    Some(&mut (_, ref mut val)) => *val = box val.reduce_in(environment, context),
+                    None => {}
+                }
+                Element::Record(fields)
+            },
+            Element::Field(ref record, ref name) => {
+                if record.is_reducible() {
+                    Element::Field(box record.reduce_in(environment, context), name.clone())
+                } else {
+                    match **record {
+                        Element::Record(ref fields) => {
+                            match fields.iter().find(|&&(ref n, _)| n == name) {
+                                Some(&(_, ref val)) => *val.clone(),
+                                None => no_such_field(record, name)
+                            }
+                        },
+                        _ => type_mismatch(self)
+                    }
+                }
+            },
+            Element::AssignField(ref record, ref name, ref val) => {
+                if val.is_reducible() {
+                    Element::AssignField(record.clone(), name.clone(), box val.reduce_in(environment, context))
+                } else {
+                    let mut fields = match environment.get(record) {
+                        Some(&box Element::Record(ref fields)) => fields.clone(),
+                        _ => return type_mismatch(self)
+                    };
+                    match fields.iter().position(|&(ref n, _)| n == name) {
+                        Some(i) => fields[i].1 = val.clone(),
+                        None => return no_such_field(&Element::Record(fields), name)
+                    }
+                    environment.insert(record.clone(), box Element::Record(fields));
+                    Element::DoNothing
+                }
+            },
+            Element::Print(ref val) => {
+                if val.is_reducible() {
+                    Element::Print(box val.reduce_in(environment, context))
+                } else {
+                    context.output.push(val.clone());
+                    Element::DoNothing
+                }
+            },
+            Element::Read(ref name) => {
+                match context.input.pop_front() {
+                    Some(val) => {
+                        environment.insert(name.clone(), val);
+                        Element::DoNothing
+                    },
+                    None => {
+                        context.waiting_for_input = true;
+                        self.clone()
+                    }
+                }
+            },
+            Element::Assert(box Element::Boolean(true), _) => {
+                Element::DoNothing
+            },
+            Element::Assert(box Element::Boolean(false), ref message) => {
+                context.failed_assertion = Some(message.clone());
+                raise(format!("assertion failed: {}", message))
+            },
+            Element::Assert(ref cond, ref message) => {
+                if cond.is_reducible() {
+                    Element::Assert(box cond.reduce_in(environment, context), message.clone())
+                } else {
+                    type_mismatch(self)
+                }
+            },
+            Element::Throw(ref val) => {
+                Element::Throw(box val.reduce_in(environment, context))
+            },
+            Element::TryCatch(ref body, ref name, ref handler) => {
+                if body.is_reducible() {
+                    Element::TryCatch(box body.reduce_in(environment, context), name.clone(), handler.clone())
+                } else {
+                    match **body {
+                        Element::Throw(ref val) => Element::Let(name.clone(), val.clone(), handler.clone()),
+                        _ => *body.clone()
+                    }
+                }
+            },
+            Element::Choose(ref left, ref right) => {
+                if context.choose(left, right) {
+                    *right.clone()
+                } else {
+                    *left.clone()
+                }
+            },
+            Element::Parallel(ref left, ref right) => {
+                if left.is_abrupt() {
+                    *left.clone()
+                } else if right.is_abrupt() {
+                    *right.clone()
+                } else if left.is_reducible() && right.is_reducible() {
+                    if context.choose(left, right) {
+                        Element::Parallel(left.clone(), box right.reduce_in(environment, context))
+                    } else {
+                        Element::Parallel(box left.reduce_in(environment, context), right.clone())
+                    }
+                } else if left.is_reducible() {
+                    Element::Parallel(box left.reduce_in(environment, context), right.clone())
+                } else if right.is_reducible() {
+                    Element::Parallel(left.clone(), box right.reduce_in(environment, context))
+                } else {
+                    Element::DoNothing
+                }
+            },
             Element::DoNothing => { Element::DoNothing }
-            _ => panic!("type mismatch in reduce: {:?}", *self)
+            _ => type_mismatch(self)
         }
     }
 }
@@ -303,10 +1085,96 @@ fn test_expression_reduces() {
     assert_eq!(false, red.is_reducible())
 }
 
+/// Turn a SIMPLE index into a position in a list of the given length, if it is in bounds.
+fn checked_index(len: usize, index: i64) -> Option<usize> {
+    if index >= 0 && (index as usize) < len {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+/// A built-in operation failed, throw an error describing why.
+fn raise(message: String) -> Element {
+    Element::Throw(box Element::Error(message))
+}
+
+fn type_mismatch(element: &Element) -> Element {
+    raise(format!("type mismatch in {:?}", element))
+}
+
+fn overflow(element: &Element) -> Element {
+    raise(format!("overflow in {:?}", element))
+}
+
+fn out_of_bounds(len: usize, index: i64) -> Element {
+    raise(format!("index {} out of bounds for list of length {}", index, len))
+}
+
+fn no_such_field(record: &Element, name: &str) -> Element {
+    raise(format!("no field {} in {:?}", name, record))
+}
+
+/// A configuration of expression and environment that can be hashed and ordered, e.g. to find
+/// configurations seen before. The environment is kept sorted by name.
+#[derive(Clone,PartialEq,Eq,Hash,PartialOrd,Ord,Debug)]
+pub struct Fingerprint {
+    pub expression: Element,
+    pub environment: BTreeMap<String, Box<Element>>
+}
+
+impl Fingerprint {
+    /// Create the fingerprint of an expression in an environment
+    pub fn new(expression: &Element, environment: &HashMap<String, Box<Element>>) -> Fingerprint {
+        Fingerprint {
+            expression: expression.clone(),
+            environment: environment.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        }
+    }
+}
+
+/// How a run of the machine ended.
+#[derive(Debug,PartialEq)]
+pub enum Outcome {
+    /// The expression was reduced completely.
+    Done,
+    /// Reduction was aborted by an uncaught exception, `break` or `continue`.
+    Error(String),
+    /// The program wants to read more input than it was given. Add more input and run the
+    /// machine again to continue.
+    InputExhausted,
+    /// An assertion failed. The machine stopped right at the assertion.
+    AssertionFailed {
+        message: String,
+        expression: Box<Element>,
+        environment: HashMap<String, Box<Element>>
+    },
+    /// The machine reached a configuration it was in before, so it would loop forever.
+    /// The configuration of `cycle_start` steps into the run was seen again `cycle_len` steps
+    /// later, the length of the cycle. The cycle may have been entered earlier.
+    Diverges {
+        cycle_start: usize,
+        cycle_len: usize
+    }
+}
+
+/// How a run ended that reduced `expression` completely.
+fn outcome(expression: &Element) -> Outcome {
+    match *expression {
+        Element::Throw(box Element::Error(ref message)) => Outcome::Error(message.clone()),
+        Element::Throw(ref val) => Outcome::Error(format!("uncaught exception {:?}", val)),
+        Element::Break => Outcome::Error("break outside of a loop".to_string()),
+        Element::Continue => Outcome::Error("continue outside of a loop".to_string()),
+        _ => Outcome::Done
+    }
+}
+
 /// Our virtual machine, executing our constructed AST step-by-step
 pub struct Machine {
     expression: Box<Element>,
-    environment: HashMap<String, Box<Element>>
+    environment: HashMap<String, Box<Element>>,
+    context: Context,
+    detect_cycles: bool
 }
 
 impl Machine {
@@ -314,7 +1182,9 @@ impl Machine {
     pub fn new(expression: Box<Element>, map: HashMap<String, Box<Element>>) -> Machine {
         Machine {
             expression: expression,
-            environment: map
+            environment: map,
+            context: Context::new(),
+            detect_cycles: false
         }
     }
 
@@ -323,7 +1193,9 @@ impl Machine {
         let map: HashMap<String, Box<Element>> = HashMap::new();
         Machine {
             expression: expression,
-            environment: map
+            environment: map,
+            context: Context::new(),
+            detect_cycles: false
         }
     }
 
@@ -332,20 +1204,149 @@ impl Machine {
         self.environment.clone()
     }
 
+    /// The current expression
+    pub fn expression(&self) -> &Element {
+        &self.expression
+    }
+
+    /// The current environment
+    pub fn environment(&self) -> &HashMap<String, Box<Element>> {
+        &self.environment
+    }
+
+    /// The current configuration of expression and environment, ready for hashing
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::new(&self.expression, &self.environment)
+    }
+
+    /// Everything the program printed so far
+    pub fn output(&self) -> &[Box<Element>] {
+        &self.context.output
+    }
+
+    /// The message of the assertion that failed in the last step, if any
+    pub fn failed_assertion(&self) -> Option<&String> {
+        self.context.failed_assertion.as_ref()
+    }
+
+    /// Wether the last step tried to read input, but there was none
+    pub fn waiting_for_input(&self) -> bool {
+        self.context.waiting_for_input
+    }
+
+    /// Let a scheduler decide nondeterministic choices and which side of a parallel element steps
+    pub fn set_scheduler(&mut self, scheduler: Box<Scheduler>) {
+        self.context.scheduler = Some(scheduler);
+    }
+
+    /// All nondeterministic decisions taken so far, `false` is left and `true` is right
+    pub fn decisions(&self) -> &[bool] {
+        &self.context.decisions
+    }
+
+    /// Take the given decisions at the next nondeterministic choices, e.g. to replay the
+    /// decisions of an earlier run
+    pub fn replay(&mut self, decisions: Vec<bool>) {
+        self.context.choices.extend(decisions.into_iter());
+    }
+
+    /// Stop `run` once a configuration of expression and environment repeats.
+    /// This remembers a single fingerprint and finds a cycle within about twice its length
+    /// after the machine entered it.
+    pub fn set_cycle_detection(&mut self, detect: bool) {
+        self.detect_cycles = detect;
+    }
+
+    /// Append values to the input of the program
+    pub fn add_input(&mut self, input: Vec<Box<Element>>) {
+        self.context.input.extend(input.into_iter());
+    }
+
     /// Reduce one step of our current expression
     pub fn step(&mut self) {
-        self.expression = box self.expression.reduce(&mut self.environment)
+        self.context.waiting_for_input = false;
+        self.context.failed_assertion = None;
+        let next = box self.expression.reduce_in(&mut self.environment, &mut self.context);
+
+        // A failed assertion keeps the machine at the assertion.
+        if self.context.failed_assertion.is_none() {
+            self.expression = next;
+        }
     }
 
     /// Reduce until we reached a non-reducible expression.
-    /// This prints the current expression before each step.
-    pub fn run(&mut self) {
+    /// This prints the current expression before each step and the output of each step after it.
+    pub fn run(&mut self) -> Outcome {
+        // Brent's algorithm: compare every configuration with a single saved one, which is
+        // replaced whenever the distance to it reaches the next power of two.
+        let mut saved: Option<(Fingerprint, usize)> = None;
+        let mut power = 1;
+        let mut steps = 0;
+
         while self.expression.is_reducible() {
             println!("{:?}", self.expression);
-            self.step()
+
+            if self.detect_cycles {
+                let fingerprint = self.fingerprint();
+                let save = match saved {
+                    Some((ref seen, cycle_start)) => {
+                        if *seen == fingerprint {
+                            println!("diverges");
+                            return Outcome::Diverges {
+                                cycle_start: cycle_start,
+                                cycle_len: steps - cycle_start
+                            };
+                        }
+                        if steps - cycle_start == power {
+                            power *= 2;
+                            true
+                        } else {
+                            false
+                        }
+                    },
+                    None => true
+                };
+                if save {
+                    saved = Some((fingerprint, steps));
+                }
+            }
+
+            let printed = self.context.output.len();
+            let unread = self.context.input.len();
+            let decided = self.context.decisions.len();
+            self.step();
+            steps += 1;
+
+            // After reading input or a nondeterministic decision, the same configuration can
+            // lead somewhere else.
+            if self.context.input.len() != unread || self.context.decisions.len() != decided {
+                saved = None;
+                power = 1;
+            }
+
+            for val in self.context.output[printed..].iter() {
+                println!("output: {:?}", val);
+            }
+            if self.context.waiting_for_input {
+                println!("waiting for input");
+                return Outcome::InputExhausted;
+            }
+            match self.context.failed_assertion {
+                Some(ref message) => {
+                    println!("assertion failed: {}", message);
+                    return Outcome::AssertionFailed {
+                        message: message.clone(),
+                        expression: self.expression.clone(),
+                        environment: self.environment.clone()
+                    };
+                },
+                None => {}
+            }
         }
 
         println!("{:?}", self.expression);
+
+        outcome(&self.expression)
     }
 }
 
@@ -632,3 +1633,829 @@ fn test_while_loops_fully_with_machine () {
 
     assert_eq!(9, env.get(&"x".to_string()).unwrap().value());
 }
+
+#[test]
+fn test_let_is_reduced() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+
+    let block = let_!("x", add!(number!(2), number!(3)), assign!("y", variable!("x")));
+    //  1. let x = 2 + 3 in [ y = x ]
+    //  2. let x = 5 in [ y = x ]
+    //  3. local x [ y = x ] restores 1
+    //  4. local x [ y = 5 ] restores 1
+    //  5. local x [ do-nothing ] restores 1
+    //  6. do-nothing
+
+    assert_eq!(true, block.is_reducible());
+    assert_eq!("let x = 2 + 3 in [ y = x ]".to_string(), format!("{:?}", block));
+
+    let block = block.reduce(&mut env);
+    assert_eq!("let x = 5 in [ y = x ]".to_string(), format!("{:?}", block));
+
+    let block = block.reduce(&mut env);
+    assert_eq!("local x [ y = x ] restores 1".to_string(), format!("{:?}", block));
+    assert_eq!(5, env.get(&"x".to_string()).unwrap().value());
+
+    let block = block.reduce(&mut env);
+    let block = block.reduce(&mut env);
+    assert_eq!("local x [ do-nothing ] restores 1".to_string(), format!("{:?}", block));
+
+    let block = block.reduce(&mut env);
+    assert_eq!(Element::DoNothing, block);
+    assert_eq!(1, env.get(&"x".to_string()).unwrap().value());
+    assert_eq!(5, env.get(&"y".to_string()).unwrap().value());
+}
+
+#[test]
+fn test_let_does_not_leak_locals() {
+    let env = HashMap::new();
+
+    //  let i = 0 in [ while (i < 3) [ i = i + 1; sum = sum + i ] ]
+    let mut m = Machine::new(
+        sequence!(
+            assign!("sum", number!(0)),
+            let_!("i", number!(0),
+                while_!(
+                    less_than!(variable!("i"), number!(3)),
+                    sequence!(
+                        assign!("i", add!(variable!("i"), number!(1))),
+                        assign!("sum", add!(variable!("sum"), variable!("i")))
+                        )
+                    )
+                )
+            ),
+            env
+        );
+
+    m.run();
+
+    let env = m.clone_env();
+
+    assert_eq!(6, env.get(&"sum".to_string()).unwrap().value());
+    assert_eq!(None, env.get(&"i".to_string()));
+}
+
+#[test]
+fn test_nested_let_shadows_and_restores() {
+    let env = HashMap::new();
+
+    let mut m = Machine::new(
+        let_!("x", number!(1),
+            sequence!(
+                let_!("x", number!(2), assign!("inner", variable!("x"))),
+                assign!("outer", variable!("x"))
+                )
+            ),
+            env
+        );
+
+    m.run();
+
+    let env = m.clone_env();
+
+    assert_eq!(2, env.get(&"inner".to_string()).unwrap().value());
+    assert_eq!(1, env.get(&"outer".to_string()).unwrap().value());
+    assert_eq!(None, env.get(&"x".to_string()));
+}
+
+#[test]
+fn test_list_is_reduced() {
+    let mut env = HashMap::new();
+    let l = list!(add!(number!(1), number!(2)), multiply!(number!(2), number!(3)));
+    //  1. [1 + 2, 2 * 3]
+    //  2. [3, 2 * 3]
+    //  3. [3, 6]
+
+    assert_eq!(true, l.is_reducible());
+    assert_eq!("[1 + 2, 2 * 3]".to_string(), format!("{:?}", l));
+
+    let l = l.reduce(&mut env);
+    assert_eq!("[3, 2 * 3]".to_string(), format!("{:?}", l));
+
+    let l = l.reduce(&mut env);
+    assert_eq!("[3, 6]".to_string(), format!("{:?}", l));
+    assert_eq!(false, l.is_reducible());
+}
+
+#[test]
+fn test_list_operations_with_machine() {
+    let mut env = HashMap::new();
+    env.insert("a".to_string(), list!(number!(3), number!(1), number!(2)));
+
+    let mut m = Machine::new(
+        sequence!(
+            assign!("a", append!(variable!("a"), number!(4))),
+            sequence!(
+                assign_index!("a", number!(1), add!(index!(variable!("a"), number!(0)), number!(10))),
+                assign!("n", length!(variable!("a")))
+                )
+            ),
+            env
+        );
+
+    assert_eq!(Outcome::Done, m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!("[3, 13, 2, 4]".to_string(), format!("{:?}", env.get(&"a".to_string()).unwrap()));
+    assert_eq!(4, env.get(&"n".to_string()).unwrap().value());
+}
+
+#[test]
+fn test_index_out_of_bounds() {
+    let mut env = HashMap::new();
+    env.insert("a".to_string(), list!(number!(1), number!(2)));
+
+    let mut m = Machine::new(
+        sequence!(
+            assign!("x", add!(number!(1), index!(variable!("a"), number!(2)))),
+            assign!("y", number!(1))
+            ),
+            env
+        );
+
+    assert_eq!(Outcome::Error("index 2 out of bounds for list of length 2".to_string()), m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!(None, env.get(&"x".to_string()));
+    assert_eq!(None, env.get(&"y".to_string()));
+}
+
+#[test]
+fn test_indexed_assignment_out_of_bounds() {
+    let mut env = HashMap::new();
+    env.insert("a".to_string(), list!(number!(1)));
+
+    let mut m = Machine::new(assign_index!("a", number!(-1), number!(5)), env);
+
+    assert_eq!(Outcome::Error("index -1 out of bounds for list of length 1".to_string()), m.run());
+}
+
+#[test]
+fn test_record_is_reduced() {
+    let mut env = HashMap::new();
+    let r = field!(record!("x" => add!(number!(1), number!(2)), "y" => number!(2)), "x");
+    //  1. { x: 1 + 2, y: 2 }.x
+    //  2. { x: 3, y: 2 }.x
+    //  3. 3
+
+    assert_eq!(true, r.is_reducible());
+    assert_eq!("{ x: 1 + 2, y: 2 }.x".to_string(), format!("{:?}", r));
+
+    let r = r.reduce(&mut env);
+    assert_eq!("{ x: 3, y: 2 }.x".to_string(), format!("{:?}", r));
+
+    let r = r.reduce(&mut env);
+    assert_eq!("3".to_string(), format!("{:?}", r));
+}
+
+#[test]
+fn test_record_field_update_with_machine() {
+    let env = HashMap::new();
+
+    let mut m = Machine::new(
+        sequence!(
+            assign!("p", record!("x" => number!(1), "y" => number!(2))),
+            assign_field!("p", "x", add!(field!(variable!("p"), "y"), number!(1)))
+            ),
+            env
+        );
+
+    assert_eq!(Outcome::Done, m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!("{ x: 3, y: 2 }".to_string(), format!("{:?}", env.get(&"p".to_string()).unwrap()));
+}
+
+#[test]
+fn test_missing_record_field() {
+    let mut env = HashMap::new();
+    env.insert("p".to_string(), record!("x" => number!(1)));
+
+    let mut m = Machine::new(assign!("z", field!(variable!("p"), "z")), env);
+    assert_eq!(Outcome::Error("no field z in { x: 1 }".to_string()), m.run());
+
+    let mut env = HashMap::new();
+    env.insert("p".to_string(), record!("x" => number!(1)));
+
+    let mut m = Machine::new(assign_field!("p", "z", number!(2)), env);
+    assert_eq!(Outcome::Error("no field z in { x: 1 }".to_string()), m.run());
+}
+
+#[test]
+fn test_break_leaves_loop() {
+    let mut env = HashMap::new();
+    env.insert("a".to_string(), list!(number!(4), number!(8), number!(15), number!(16)));
+    env.insert("i".to_string(), number!(0));
+
+    //  while (i < length(a)) [ if (15 < a[i] + 1) [ break ] else [ i = i + 1 ] ]
+    let mut m = Machine::new(
+        while_!(
+            less_than!(variable!("i"), length!(variable!("a"))),
+            ifelse!(
+                less_than!(number!(15), add!(index!(variable!("a"), variable!("i")), number!(1))),
+                break_!(),
+                assign!("i", add!(variable!("i"), number!(1)))
+                )
+            ),
+            env
+        );
+
+    assert_eq!(Outcome::Done, m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!(2, env.get(&"i".to_string()).unwrap().value());
+}
+
+#[test]
+fn test_continue_skips_rest_of_body() {
+    let mut env = HashMap::new();
+    env.insert("i".to_string(), number!(0));
+    env.insert("sum".to_string(), number!(0));
+
+    //  while (i < 5) [ i = i + 1; if (i < 3) [ continue ]; sum = sum + i ]
+    let mut m = Machine::new(
+        while_!(
+            less_than!(variable!("i"), number!(5)),
+            sequence!(
+                assign!("i", add!(variable!("i"), number!(1))),
+                sequence!(
+                    if_!(less_than!(variable!("i"), number!(3)), continue_!()),
+                    assign!("sum", add!(variable!("sum"), variable!("i")))
+                    )
+                )
+            ),
+            env
+        );
+
+    assert_eq!(Outcome::Done, m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!(5, env.get(&"i".to_string()).unwrap().value());
+    assert_eq!(12, env.get(&"sum".to_string()).unwrap().value());
+}
+
+#[test]
+fn test_break_restores_local_variables() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+
+    let mut m = Machine::new(
+        while_!(
+            boolean!(true),
+            let_!("x", number!(2), sequence!(break_!(), assign!("x", number!(3))))
+            ),
+            env
+        );
+
+    assert_eq!(Outcome::Done, m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!(1, env.get(&"x".to_string()).unwrap().value());
+}
+
+#[test]
+fn test_break_and_continue_outside_of_loop() {
+    let mut m = Machine::new_with_empty_env(
+        sequence!(break_!(), assign!("x", number!(1)))
+        );
+    assert_eq!(Outcome::Error("break outside of a loop".to_string()), m.run());
+    assert_eq!(None, m.clone_env().get(&"x".to_string()));
+
+    let mut m = Machine::new_with_empty_env(if_!(boolean!(true), continue_!()));
+    assert_eq!(Outcome::Error("continue outside of a loop".to_string()), m.run());
+}
+
+#[test]
+fn test_for_loops() {
+    let mut env = HashMap::new();
+    let for_loop = for_!(
+        assign!("i", number!(0)),
+        less_than!(variable!("i"), number!(2)),
+        assign!("i", add!(variable!("i"), number!(1))),
+        assign!("x", variable!("i"))
+        );
+    //  1. for (i = 0; i < 2; i = i + 1) [ x = i ]
+    //  2. for (do-nothing; i < 2; i = i + 1) [ x = i ]
+    //  3. if (i < 2) [ x = i; i = i + 1; for (do-nothing; i < 2; i = i + 1) [ x = i ] ] else [ do-nothing ]
+
+    assert_eq!(true, for_loop.is_reducible());
+    assert_eq!("for (i = 0; i < 2; i = i + 1) [ x = i ]".to_string(), format!("{:?}", for_loop));
+
+    let for_loop = for_loop.reduce(&mut env);
+    assert_eq!("for (do-nothing; i < 2; i = i + 1) [ x = i ]".to_string(), format!("{:?}", for_loop));
+
+    let for_loop = for_loop.reduce(&mut env);
+    assert_eq!(
+        "if (i < 2) [ x = i; i = i + 1; for (do-nothing; i < 2; i = i + 1) [ x = i ] ] else [ do-nothing ]".to_string(),
+        format!("{:?}", for_loop));
+}
+
+#[test]
+fn test_for_loops_fully_with_machine() {
+    let mut env = HashMap::new();
+    env.insert("sum".to_string(), number!(0));
+
+    //  for (i = 0; i < 6; i = i + 1) [ if (i < 3) [ continue ]; sum = sum + i ]
+    let mut m = Machine::new(
+        for_!(
+            assign!("i", number!(0)),
+            less_than!(variable!("i"), number!(6)),
+            assign!("i", add!(variable!("i"), number!(1))),
+            sequence!(
+                if_!(less_than!(variable!("i"), number!(3)), continue_!()),
+                assign!("sum", add!(variable!("sum"), variable!("i")))
+                )
+            ),
+            env
+        );
+
+    assert_eq!(Outcome::Done, m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!(6, env.get(&"i".to_string()).unwrap().value());
+    assert_eq!(12, env.get(&"sum".to_string()).unwrap().value());
+}
+
+#[test]
+fn test_do_while_runs_body_once() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(10));
+
+    let do_while = do_while!(
+        assign!("x", multiply!(variable!("x"), number!(2))),
+        less_than!(variable!("x"), number!(5))
+        );
+    //  1. do [ x = x * 2 ] while (x < 5)
+    //  2. x = x * 2; while (x < 5) [ x = x * 2 ]
+
+    assert_eq!("do [ x = x * 2 ] while (x < 5)".to_string(), format!("{:?}", do_while));
+
+    let do_while = do_while.reduce(&mut env);
+    assert_eq!("x = x * 2; while (x < 5) [ x = x * 2 ]".to_string(), format!("{:?}", do_while));
+
+    let mut m = Machine::new(box do_while, env);
+
+    assert_eq!(Outcome::Done, m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!(20, env.get(&"x".to_string()).unwrap().value());
+}
+
+#[test]
+fn test_print_is_reduced() {
+    let mut env = HashMap::new();
+    let mut context = Context::new();
+    let p = print_!(add!(number!(1), number!(2)));
+    //  1. print 1 + 2
+    //  2. print 3
+    //  3. do-nothing
+
+    assert_eq!(true, p.is_reducible());
+    assert_eq!("print 1 + 2".to_string(), format!("{:?}", p));
+
+    let p = p.reduce_in(&mut env, &mut context);
+    assert_eq!("print 3".to_string(), format!("{:?}", p));
+    assert_eq!(0, context.output.len());
+
+    let p = p.reduce_in(&mut env, &mut context);
+    assert_eq!(Element::DoNothing, p);
+    assert_eq!(vec![number!(3)], context.output);
+}
+
+#[test]
+fn test_print_output_is_captured_by_machine() {
+    let mut env = HashMap::new();
+    env.insert("i".to_string(), number!(0));
+
+    //  while (i < 3) [ i = i + 1; print i * i ]
+    let mut m = Machine::new(
+        while_!(
+            less_than!(variable!("i"), number!(3)),
+            sequence!(
+                assign!("i", add!(variable!("i"), number!(1))),
+                print_!(multiply!(variable!("i"), variable!("i")))
+                )
+            ),
+            env
+        );
+
+    assert_eq!(0, m.output().len());
+    assert_eq!(Outcome::Done, m.run());
+
+    let output: Vec<i64> = m.output().iter().map(|v| v.value()).collect();
+    assert_eq!(vec![1, 4, 9], output);
+}
+
+#[test]
+fn test_read_assigns_input() {
+    let mut env = HashMap::new();
+    let mut context = Context::new();
+    context.input.push_back(number!(7));
+    let r = read!("x");
+
+    assert_eq!(true, r.is_reducible());
+    assert_eq!("read x".to_string(), format!("{:?}", r));
+
+    let r = r.reduce_in(&mut env, &mut context);
+    assert_eq!(Element::DoNothing, r);
+    assert_eq!(7, env.get(&"x".to_string()).unwrap().value());
+    assert_eq!(false, context.waiting_for_input);
+
+    let r = read!("y").reduce_in(&mut env, &mut context);
+    assert_eq!("read y".to_string(), format!("{:?}", r));
+    assert_eq!(true, context.waiting_for_input);
+}
+
+#[test]
+fn test_machine_reads_scripted_input() {
+    let env = HashMap::new();
+
+    //  read n; sum = 0; while (0 < n) [ read x; sum = sum + x; n = n + -1 ]
+    let mut m = Machine::new(
+        sequence!(
+            read!("n"),
+            sequence!(
+                assign!("sum", number!(0)),
+                while_!(
+                    less_than!(number!(0), variable!("n")),
+                    sequence!(
+                        read!("x"),
+                        sequence!(
+                            assign!("sum", add!(variable!("sum"), variable!("x"))),
+                            assign!("n", add!(variable!("n"), number!(-1)))
+                            )
+                        )
+                    )
+                )
+            ),
+            env
+        );
+    m.add_input(vec![number!(3), number!(10), number!(20)]);
+
+    assert_eq!(Outcome::InputExhausted, m.run());
+    assert_eq!(30, m.clone_env().get(&"sum".to_string()).unwrap().value());
+
+    m.add_input(vec![number!(12)]);
+
+    assert_eq!(Outcome::Done, m.run());
+    assert_eq!(42, m.clone_env().get(&"sum".to_string()).unwrap().value());
+}
+
+#[test]
+fn test_assert_is_reduced() {
+    let mut env = HashMap::new();
+    let mut context = Context::new();
+    let a = assert_!(less_than!(number!(1), number!(2)), "one is less than two");
+
+    assert_eq!(true, a.is_reducible());
+    assert_eq!("assert (1 < 2, \"one is less than two\")".to_string(), format!("{:?}", a));
+
+    let a = a.reduce_in(&mut env, &mut context);
+    assert_eq!("assert (true, \"one is less than two\")".to_string(), format!("{:?}", a));
+
+    let a = a.reduce_in(&mut env, &mut context);
+    assert_eq!(Element::DoNothing, a);
+    assert_eq!(None, context.failed_assertion);
+
+    let a = assert_!(boolean!(false), "oops").reduce_in(&mut env, &mut context);
+    assert_eq!(Element::Throw(box Element::Error("assertion failed: oops".to_string())), a);
+    assert_eq!(Some("oops".to_string()), context.failed_assertion);
+}
+
+#[test]
+fn test_failed_assertion_stops_machine() {
+    let env = HashMap::new();
+
+    let mut m = Machine::new(
+        sequence!(
+            assign!("x", number!(3)),
+            sequence!(
+                assert_!(less_than!(variable!("x"), number!(3)), "x is small"),
+                assign!("y", number!(1))
+                )
+            ),
+            env
+        );
+
+    let mut expected_env = HashMap::new();
+    expected_env.insert("x".to_string(), number!(3));
+
+    assert_eq!(
+        Outcome::AssertionFailed {
+            message: "x is small".to_string(),
+            expression: sequence!(assert_!(boolean!(false), "x is small"), assign!("y", number!(1))),
+            environment: expected_env
+        },
+        m.run());
+}
+
+#[test]
+fn test_loop_invariant_is_checked_on_every_unfolding() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+
+    //  while (x < 20) invariant (x < 10) [ x = x * 3 ]
+    let while_loop = while_invariant!(
+        less_than!(variable!("x"), number!(20)),
+        less_than!(variable!("x"), number!(10)),
+        assign!("x", multiply!(variable!("x"), number!(3)))
+        );
+    assert_eq!("while (x < 20) invariant (x < 10) [ x = x * 3 ]".to_string(), format!("{:?}", while_loop));
+
+    let mut m = Machine::new(while_loop, env);
+
+    match m.run() {
+        Outcome::AssertionFailed { message, environment, .. } => {
+            assert_eq!("loop invariant x < 10".to_string(), message);
+            assert_eq!(27, environment.get(&"x".to_string()).unwrap().value());
+        },
+        outcome => panic!("expected a failed assertion, got {:?}", outcome)
+    }
+}
+
+#[test]
+fn test_throw_propagates_outwards() {
+    let mut env = HashMap::new();
+    let t = sequence!(assign!("x", add!(number!(1), throw!(add!(number!(1), number!(1))))), assign!("y", number!(2)));
+    //  1. x = 1 + throw 1 + 1; y = 2
+    //  2. x = 1 + throw 2; y = 2
+    //  3. x = throw 2; y = 2
+    //  4. throw 2; y = 2
+    //  5. throw 2
+
+    assert_eq!("x = 1 + throw 1 + 1; y = 2".to_string(), format!("{:?}", t));
+
+    let t = t.reduce(&mut env);
+    assert_eq!("x = 1 + throw 2; y = 2".to_string(), format!("{:?}", t));
+    let t = t.reduce(&mut env);
+    assert_eq!("x = throw 2; y = 2".to_string(), format!("{:?}", t));
+    let t = t.reduce(&mut env);
+    assert_eq!("throw 2; y = 2".to_string(), format!("{:?}", t));
+    let t = t.reduce(&mut env);
+    assert_eq!("throw 2".to_string(), format!("{:?}", t));
+    assert_eq!(false, t.is_reducible());
+    assert_eq!(true, t.is_abrupt());
+    assert_eq!(0, env.len());
+}
+
+#[test]
+fn test_try_catch_binds_thrown_value() {
+    let env = HashMap::new();
+
+    let mut m = Machine::new(
+        sequence!(
+            try_catch!(
+                sequence!(throw!(number!(42)), assign!("x", number!(1))),
+                "e",
+                assign!("caught", variable!("e"))
+                ),
+            assign!("after", number!(1))
+            ),
+            env
+        );
+
+    assert_eq!(Outcome::Done, m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!(42, env.get(&"caught".to_string()).unwrap().value());
+    assert_eq!(1, env.get(&"after".to_string()).unwrap().value());
+    assert_eq!(None, env.get(&"x".to_string()));
+    assert_eq!(None, env.get(&"e".to_string()));
+}
+
+#[test]
+fn test_builtin_errors_are_catchable() {
+    let mut env = HashMap::new();
+    env.insert("a".to_string(), list!(number!(1)));
+
+    let mut m = Machine::new(
+        sequence!(
+            try_catch!(assign!("x", index!(variable!("a"), number!(5))), "e", assign!("oob", variable!("e"))),
+            sequence!(
+                try_catch!(assign!("y", add!(number!(1), list!())), "e", assign!("y", variable!("e"))),
+                sequence!(
+                    try_catch!(assign!("z", index!(number!(1), number!(0))), "e", assign!("z", variable!("e"))),
+                    sequence!(
+                        try_catch!(assign!("u", index!(variable!("a"), variable!("unset"))), "e", assign!("u", variable!("e"))),
+                        try_catch!(assign_index!("a", list!(), number!(2)), "e", assign!("v", variable!("e")))
+                        )
+                    )
+                )
+            ),
+            env
+        );
+
+    assert_eq!(Outcome::Done, m.run());
+
+    let env = m.clone_env();
+
+    assert_eq!(
+        "error(\"index 5 out of bounds for list of length 1\")".to_string(),
+        format!("{:?}", env.get(&"oob".to_string()).unwrap()));
+    assert_eq!(
+        "error(\"type mismatch in 1 + []\")".to_string(),
+        format!("{:?}", env.get(&"y".to_string()).unwrap()));
+    assert_eq!(
+        "error(\"type mismatch in 1[0]\")".to_string(),
+        format!("{:?}", env.get(&"z".to_string()).unwrap()));
+    assert_eq!(
+        "error(\"type mismatch in [1][do-nothing]\")".to_string(),
+        format!("{:?}", env.get(&"u".to_string()).unwrap()));
+    assert_eq!(
+        "error(\"type mismatch in a[[]] = 2\")".to_string(),
+        format!("{:?}", env.get(&"v".to_string()).unwrap()));
+    assert_eq!(&list!(number!(1)), env.get(&"a".to_string()).unwrap());
+}
+
+#[test]
+fn test_booleans_are_no_list_indices() {
+    let mut env = HashMap::new();
+    env.insert("a".to_string(), list!(number!(1), number!(2)));
+
+    let mut m = Machine::new(index!(variable!("a"), boolean!(true)), env.clone());
+    assert_eq!(Outcome::Error("type mismatch in [1, 2][true]".to_string()), m.run());
+
+    let mut m = Machine::new(assign_index!("a", boolean!(false), number!(3)), env);
+    assert_eq!(Outcome::Error("type mismatch in a[false] = 3".to_string()), m.run());
+    assert_eq!(&list!(number!(1), number!(2)), m.clone_env().get(&"a".to_string()).unwrap());
+}
+
+#[test]
+fn test_uncaught_exceptions() {
+    let mut m = Machine::new_with_empty_env(
+        add!(number!(1), less_than!(list!(), number!(2)))
+        );
+    assert_eq!(Outcome::Error("type mismatch in [] < 2".to_string()), m.run());
+
+    let mut m = Machine::new_with_empty_env(
+        multiply!(number!(::std::i64::MAX), number!(2))
+        );
+    assert_eq!(Outcome::Error("overflow in 9223372036854775807 * 2".to_string()), m.run());
+
+    let mut m = Machine::new_with_empty_env(
+        try_catch!(throw!(number!(1)), "e", throw!(add!(variable!("e"), number!(1))))
+        );
+    assert_eq!(Outcome::Error("uncaught exception 2".to_string()), m.run());
+}
+
+#[test]
+fn test_parallel_is_reduced() {
+    let mut env = HashMap::new();
+    let mut context = Context::new();
+    context.choices.push_back(true);
+    let p = parallel!(add!(number!(1), number!(2)), add!(number!(3), number!(4)));
+    //  1. [ 1 + 2 ] || [ 3 + 4 ]
+    //  2. [ 1 + 2 ] || [ 7 ]
+    //  3. [ 3 ] || [ 7 ]
+    //  4. do-nothing
+
+    assert_eq!(true, p.is_reducible());
+    assert_eq!("[ 1 + 2 ] || [ 3 + 4 ]".to_string(), format!("{:?}", p));
+
+    let p = p.reduce_in(&mut env, &mut context);
+    assert_eq!("[ 1 + 2 ] || [ 7 ]".to_string(), format!("{:?}", p));
+
+    let p = p.reduce_in(&mut env, &mut context);
+    assert_eq!("[ 3 ] || [ 7 ]".to_string(), format!("{:?}", p));
+    assert_eq!(vec![true], context.decisions);
+    assert_eq!(true, p.is_reducible());
+
+    let p = p.reduce_in(&mut env, &mut context);
+    assert_eq!(Element::DoNothing, p);
+}
+
+#[test]
+fn test_parallel_race_condition() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(0));
+
+    //  [ x = x + 1 ] || [ x = x + 1 ]
+    let program = parallel!(
+        assign!("x", add!(variable!("x"), number!(1))),
+        assign!("x", add!(variable!("x"), number!(1)))
+        );
+
+    let mut m = Machine::new(program.clone(), env.clone());
+    m.set_scheduler(box schedule::RoundRobin::new());
+    assert_eq!(Outcome::Done, m.run());
+    assert_eq!(&Element::DoNothing, m.expression());
+    assert_eq!(1, m.clone_env().get(&"x".to_string()).unwrap().value());
+    assert_eq!(&[false, true, false, true, false], m.decisions());
+
+    let mut m = Machine::new(program.clone(), env.clone());
+    m.replay(vec![false, false, false]);
+    assert_eq!(Outcome::Done, m.run());
+    assert_eq!(2, m.clone_env().get(&"x".to_string()).unwrap().value());
+
+    let mut xs: Vec<i64> = explore::explore(&program, &env, 100).finals.iter()
+        .map(|&(_, ref env)| env.get(&"x".to_string()).unwrap().value())
+        .collect();
+    xs.sort();
+    xs.dedup();
+    assert_eq!(vec![1, 2], xs);
+}
+
+#[test]
+fn test_elements_can_be_hashed_and_ordered() {
+    use std::collections::HashSet;
+
+    let mut set = HashSet::new();
+    set.insert(add!(number!(1), variable!("x")));
+    set.insert(add!(number!(1), variable!("x")));
+    set.insert(add!(variable!("x"), number!(1)));
+
+    assert_eq!(2, set.len());
+    assert!(set.contains(&add!(variable!("x"), number!(1))));
+
+    let mut elements = vec![number!(3), number!(-1), number!(2)];
+    elements.sort();
+    assert_eq!(vec![number!(-1), number!(2), number!(3)], elements);
+}
+
+#[test]
+fn test_fingerprint_ignores_insertion_order() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+    env.insert("y".to_string(), number!(2));
+    let m = Machine::new(assign!("z", variable!("x")), env);
+
+    let mut env = HashMap::new();
+    env.insert("y".to_string(), number!(2));
+    env.insert("x".to_string(), number!(1));
+    let n = Machine::new(assign!("z", variable!("x")), env);
+
+    assert_eq!(m.fingerprint(), n.fingerprint());
+
+    let mut seen = ::std::collections::HashSet::new();
+    seen.insert(m.fingerprint());
+    assert!(seen.contains(&n.fingerprint()));
+}
+
+#[test]
+fn test_cycle_detection() {
+    let mut m = Machine::new_with_empty_env(
+        while_!(boolean!(true), box Element::DoNothing)
+        );
+    m.set_cycle_detection(true);
+    //  0. while (true) [ do-nothing ]
+    //  1. if (true) [ do-nothing; while (true) [ do-nothing ] ] else [ do-nothing ]
+    //  2. do-nothing; while (true) [ do-nothing ]
+    //  3. while (true) [ do-nothing ]
+    // Step 3 is saved, with step 1 saved before, and seen again at step 6.
+
+    assert_eq!(Outcome::Diverges { cycle_start: 3, cycle_len: 3 }, m.run());
+
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+
+    //  x = 0; while (true) [ x = 1 - x ]
+    let mut m = Machine::new(
+        sequence!(
+            assign!("x", number!(0)),
+            while_!(boolean!(true), assign!("x", add!(number!(1), multiply!(number!(-1), variable!("x")))))
+            ),
+            env
+        );
+    m.set_cycle_detection(true);
+
+    match m.run() {
+        Outcome::Diverges { cycle_start, cycle_len } => {
+            // x = 0 after the assignment at step 2, two iterations of seven steps later again.
+            // The configuration saved at step 15 is on the cycle.
+            assert_eq!(15, cycle_start);
+            assert_eq!(2 * 7, cycle_len);
+        },
+        outcome => panic!("expected divergence, got {:?}", outcome)
+    }
+}
+
+#[test]
+fn test_cycle_detection_ignores_terminating_and_reading_loops() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+
+    let mut m = Machine::new(
+        while_!(
+            less_than!(variable!("x"), number!(5)),
+            assign!("x", multiply!(variable!("x"), number!(3)))
+            ),
+            env
+        );
+    m.set_cycle_detection(true);
+    assert_eq!(Outcome::Done, m.run());
+
+    let mut m = Machine::new_with_empty_env(while_!(boolean!(true), read!("x")));
+    m.set_cycle_detection(true);
+    m.add_input(vec![number!(1), number!(1), number!(1)]);
+    assert_eq!(Outcome::InputExhausted, m.run());
+}
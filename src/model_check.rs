@@ -0,0 +1,348 @@
+//! An explicit-state model checker for SIMPLE programs.
+//!
+//! Input variables are given finite domains. The program is run on a machine for every
+//! combination of initial values, and a safety predicate is checked on every configuration of
+//! expression and environment along the way. A failed assertion or a read without input is a
+//! violation as well. A configuration seen before, in the same run or an earlier one that was not
+//! cut off, is not followed again: everything reachable from it was already checked.
+//!
+//! ```ignore
+//! let mut checker = ModelChecker::new();
+//! checker.range("x", 0, 10);
+//! checker.values("flag", vec![boolean!(true), boolean!(false)]);
+//!
+//! match checker.check(&program, |_, env| env.get("y").map_or(true, |y| y.value() < 15)) {
+//!     Verdict::Unsafe(counterexample) => print!("{}", counterexample.trace),
+//!     Verdict::Safe { .. } => println!("safe")
+//! }
+//! ```
+
+use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
+
+use {Element, Machine};
+
+/// How a counterexample went wrong.
+#[derive(Debug,PartialEq)]
+pub enum Violation {
+    /// The safety predicate does not hold on the last configuration.
+    Predicate,
+    /// An assertion failed with this message.
+    Assertion(String),
+    /// The program tried to read input, but there was none.
+    InputExhausted
+}
+
+/// A run that reached a violation.
+#[derive(Debug)]
+pub struct Counterexample {
+    /// What went wrong.
+    pub violation: Violation,
+    /// The initial environment of the run.
+    pub environment: HashMap<String, Box<Element>>,
+    /// The run up to the violation, printed just like `Machine::run` prints it.
+    pub trace: String
+}
+
+/// The result of model checking a program.
+#[derive(Debug)]
+pub enum Verdict {
+    /// The predicate held on every configuration reached.
+    Safe {
+        /// The number of initial environments checked.
+        runs: usize,
+        /// The number of distinct configurations checked in runs that were not cut off.
+        states: usize,
+        /// Wether some run was cut off after the maximum number of steps.
+        truncated: bool
+    },
+    /// The predicate was violated, an assertion failed or input ran out.
+    Unsafe(Counterexample)
+}
+
+/// Checks a program for every combination of initial values of its input variables.
+pub struct ModelChecker {
+    domains: Vec<(String, Vec<Box<Element>>)>,
+    max_steps: usize
+}
+
+impl ModelChecker {
+    /// Create a new model checker without any input variables
+    pub fn new() -> ModelChecker {
+        ModelChecker {
+            domains: Vec::new(),
+            max_steps: 10000
+        }
+    }
+
+    /// Let the variable start with any number from `from` up to, but not including, `to`
+    pub fn range(&mut self, name: &str, from: i64, to: i64) {
+        let values = (from..to).map(|n| box Element::Number(n)).collect();
+        self.values(name, values);
+    }
+
+    /// Let the variable start with any of the given values
+    pub fn values(&mut self, name: &str, values: Vec<Box<Element>>) {
+        self.domains.push((name.to_string(), values));
+    }
+
+    /// Stop following a single run after this many steps
+    pub fn max_steps(&mut self, max_steps: usize) {
+        self.max_steps = max_steps;
+    }
+
+    /// All initial environments, one for every combination of values.
+    fn environments(&self) -> Vec<HashMap<String, Box<Element>>> {
+        let mut environments = vec![HashMap::new()];
+        for &(ref name, ref values) in self.domains.iter() {
+            let mut extended = Vec::new();
+            for environment in environments.iter() {
+                for value in values.iter() {
+                    let mut environment = environment.clone();
+                    environment.insert(name.clone(), value.clone());
+                    extended.push(environment);
+                }
+            }
+            environments = extended;
+        }
+        environments
+    }
+
+    /// Run the program for every initial environment, checking `safe` on every configuration
+    pub fn check<F>(&self, program: &Element, safe: F) -> Verdict
+        where F: Fn(&Element, &HashMap<String, Box<Element>>) -> bool {
+        let mut seen = HashSet::new();
+        let mut runs = 0;
+        let mut truncated = false;
+
+        for environment in self.environments().into_iter() {
+            runs += 1;
+            let mut m = Machine::new(box program.clone(), environment.clone());
+            let mut trace = String::new();
+            let mut steps = 0;
+            let mut visited = Vec::new();
+
+            loop {
+                let fingerprint = m.fingerprint();
+                if seen.contains(&fingerprint) {
+                    break;
+                }
+                seen.insert(fingerprint.clone());
+                visited.push(fingerprint);
+
+                trace.push_str(&format!("{:?}\n", m.expression()));
+                if !safe(m.expression(), m.environment()) {
+                    return Verdict::Unsafe(Counterexample {
+                        violation: Violation::Predicate,
+                        environment: environment,
+                        trace: trace
+                    });
+                }
+
+                if !m.expression().is_reducible() {
+                    break;
+                }
+                if steps == self.max_steps {
+                    // Whatever follows was not checked, a later run has to go on from here.
+                    for fingerprint in visited.iter() {
+                        seen.remove(fingerprint);
+                    }
+                    truncated = true;
+                    break;
+                }
+
+                let printed = m.output().len();
+                m.step();
+                steps += 1;
+                for val in m.output()[printed..].iter() {
+                    trace.push_str(&format!("output: {:?}\n", val));
+                }
+
+                let violation = if m.waiting_for_input() {
+                    trace.push_str("waiting for input\n");
+                    Violation::InputExhausted
+                } else {
+                    match m.failed_assertion() {
+                        Some(message) => {
+                            trace.push_str(&format!("assertion failed: {}\n", message));
+                            Violation::Assertion(message.clone())
+                        },
+                        None => continue
+                    }
+                };
+                return Verdict::Unsafe(Counterexample {
+                    violation: violation,
+                    environment: environment,
+                    trace: trace
+                });
+            }
+        }
+
+        Verdict::Safe {
+            runs: runs,
+            states: seen.len(),
+            truncated: truncated
+        }
+    }
+}
+
+#[test]
+fn test_model_check_finds_counterexample() {
+    let mut checker = ModelChecker::new();
+    checker.range("x", 0, 10);
+
+    let program = sequence!(
+        print_!(variable!("x")),
+        assign!("y", multiply!(variable!("x"), number!(2)))
+        );
+
+    let verdict = checker.check(&program, |_, env| {
+        env.get("y").map_or(true, |y| y.value() < 15)
+    });
+
+    match verdict {
+        Verdict::Unsafe(counterexample) => {
+            assert_eq!(Violation::Predicate, counterexample.violation);
+            assert_eq!(8, counterexample.environment.get("x").unwrap().value());
+            assert_eq!(
+                "print x; y = x * 2\n\
+                 print 8; y = x * 2\n\
+                 output: 8\n\
+                 do-nothing; y = x * 2\n\
+                 y = x * 2\n\
+                 y = 8 * 2\n\
+                 y = 16\n\
+                 do-nothing\n".to_string(),
+                counterexample.trace);
+        },
+        verdict => panic!("expected a counterexample, got {:?}", verdict)
+    }
+}
+
+#[test]
+fn test_model_check_safe_program() {
+    let mut checker = ModelChecker::new();
+    checker.range("x", 0, 5);
+    checker.values("flag", vec![boolean!(true), boolean!(false)]);
+
+    //  if (flag) [ y = x ] else [ y = 0 ]
+    let program = ifelse!(
+        variable!("flag"),
+        assign!("y", variable!("x")),
+        assign!("y", number!(0))
+        );
+
+    let verdict = checker.check(&program, |_, env| {
+        env.get("y").map_or(true, |y| y.value() < 5)
+    });
+
+    match verdict {
+        Verdict::Safe { runs, truncated, .. } => {
+            assert_eq!(10, runs);
+            assert_eq!(false, truncated);
+        },
+        verdict => panic!("expected the program to be safe, got {:?}", verdict)
+    }
+}
+
+#[test]
+fn test_model_check_deduplicates_across_runs() {
+    let mut checker = ModelChecker::new();
+    checker.values("flag", vec![boolean!(true), boolean!(false)]);
+
+    //  flag = false; x = 1
+    let program = sequence!(assign!("flag", boolean!(false)), assign!("x", number!(1)));
+
+    match checker.check(&program, |_, _| true) {
+        Verdict::Safe { runs, states, .. } => {
+            assert_eq!(2, runs);
+            assert_eq!(5, states);
+        },
+        verdict => panic!("expected the program to be safe, got {:?}", verdict)
+    }
+}
+
+#[test]
+fn test_model_check_stops_non_terminating_runs() {
+    let mut checker = ModelChecker::new();
+    checker.range("x", 0, 2);
+    checker.max_steps(100);
+
+    let program = while_!(boolean!(true), assign!("x", add!(variable!("x"), number!(1))));
+
+    match checker.check(&program, |_, _| true) {
+        Verdict::Safe { truncated, .. } => assert_eq!(true, truncated),
+        verdict => panic!("expected the program to be safe, got {:?}", verdict)
+    }
+
+    let program = while_!(boolean!(true), box Element::DoNothing);
+
+    match checker.check(&program, |_, _| true) {
+        Verdict::Safe { truncated, .. } => assert_eq!(false, truncated),
+        verdict => panic!("expected the program to be safe, got {:?}", verdict)
+    }
+}
+
+#[test]
+fn test_model_check_reports_failed_assertions() {
+    let mut checker = ModelChecker::new();
+    checker.range("x", 0, 5);
+
+    let program = assert_!(less_than!(variable!("x"), number!(3)), "x is too big");
+
+    match checker.check(&program, |_, _| true) {
+        Verdict::Unsafe(counterexample) => {
+            assert_eq!(Violation::Assertion("x is too big".to_string()), counterexample.violation);
+            assert_eq!(3, counterexample.environment.get("x").unwrap().value());
+            assert_eq!(
+                "assert (x < 3, \"x is too big\")\n\
+                 assert (3 < 3, \"x is too big\")\n\
+                 assert (false, \"x is too big\")\n\
+                 assertion failed: x is too big\n".to_string(),
+                counterexample.trace);
+        },
+        verdict => panic!("expected a counterexample, got {:?}", verdict)
+    }
+}
+
+#[test]
+fn test_model_check_reports_exhausted_input() {
+    let checker = ModelChecker::new();
+
+    let program = sequence!(assign!("x", number!(1)), read!("y"));
+
+    match checker.check(&program, |_, _| true) {
+        Verdict::Unsafe(counterexample) => {
+            assert_eq!(Violation::InputExhausted, counterexample.violation);
+            assert_eq!(
+                "x = 1; read y\n\
+                 do-nothing; read y\n\
+                 read y\n\
+                 waiting for input\n".to_string(),
+                counterexample.trace);
+        },
+        verdict => panic!("expected a counterexample, got {:?}", verdict)
+    }
+}
+
+#[test]
+fn test_model_check_follows_configurations_of_truncated_runs() {
+    let mut checker = ModelChecker::new();
+    checker.values("x", vec![number!(0), number!(6)]);
+    checker.max_steps(60);
+
+    //  while (x < 12) [ x = x + 1 ]; assert x < 12
+    // The first run is cut off after reaching the start of the second one.
+    let program = sequence!(
+        while_!(less_than!(variable!("x"), number!(12)), assign!("x", add!(variable!("x"), number!(1)))),
+        assert_!(less_than!(variable!("x"), number!(12)), "x is too big")
+        );
+
+    match checker.check(&program, |_, _| true) {
+        Verdict::Unsafe(counterexample) => {
+            assert_eq!(Violation::Assertion("x is too big".to_string()), counterexample.violation);
+            assert_eq!(6, counterexample.environment.get("x").unwrap().value());
+        },
+        verdict => panic!("expected a counterexample, got {:?}", verdict)
+    }
+}
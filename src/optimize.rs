@@ -0,0 +1,852 @@
+//! Source-to-source optimizations of SIMPLE programs.
+//!
+//! Every optimization preserves the meaning of a program: run on a machine, the optimized
+//! program ends with the same result, environment and output as the original, only in fewer
+//! steps.
+
+use std::collections::HashSet;
+
+use Element;
+
+/// Simplify an element bottom-up.
+///
+/// * `+`, `*` and `<` of two numbers or booleans are folded, unless that would overflow.
+/// * `x * 1`, `1 * x`, `x + 0` and `0 + x` become `x` if `x` is an addition or
+///   multiplication, which always results in a number. A variable may hold anything, so
+///   `x * 1` stays as it is: it fails if `x` is not a number, while `x` alone would not.
+/// * `x * 0` only folds if `x` is a number or boolean as well, anything else could fail.
+/// * `do-nothing; s` becomes `s`.
+/// * An `if` on a boolean literal becomes the branch taken.
+pub fn simplify(element: &Element) -> Element {
+    match *element {
+        Element::Add(ref l, ref r) => {
+            let (l, r) = (simplify(l), simplify(r));
+            match (l.number(), r.number()) {
+                (Some(a), Some(b)) if a.checked_add(b).is_some() => Element::Number(a + b),
+                (Some(0), _) if yields_number(&r) => r,
+                (_, Some(0)) if yields_number(&l) => l,
+                _ => Element::Add(box l, box r)
+            }
+        },
+        Element::Multiply(ref l, ref r) => {
+            let (l, r) = (simplify(l), simplify(r));
+            match (l.number(), r.number()) {
+                (Some(a), Some(b)) if a.checked_mul(b).is_some() => Element::Number(a * b),
+                (Some(1), _) if yields_number(&r) => r,
+                (_, Some(1)) if yields_number(&l) => l,
+                _ => Element::Multiply(box l, box r)
+            }
+        },
+        Element::LessThan(ref l, ref r) => {
+            let (l, r) = (simplify(l), simplify(r));
+            match (l.number(), r.number()) {
+                (Some(a), Some(b)) => Element::Boolean(a < b),
+                _ => Element::LessThan(box l, box r)
+            }
+        },
+        Element::Sequence(ref first, ref second) => {
+            match simplify(first) {
+                Element::DoNothing => simplify(second),
+                first => Element::Sequence(box first, box simplify(second))
+            }
+        },
+        Element::IfElse(ref cond, ref cons, ref alt) => {
+            match simplify(cond) {
+                Element::Boolean(true) => simplify(cons),
+                Element::Boolean(false) => simplify(alt),
+                cond => Element::IfElse(box cond, box simplify(cons), box simplify(alt))
+            }
+        },
+        _ => element.map_children(simplify)
+    }
+}
+
+/// The result of dead code elimination.
+#[derive(Debug)]
+pub struct Elimination {
+    /// The program without the removed parts.
+    pub program: Element,
+    /// The assignments removed because their value is never read, in program order.
+    pub dead_stores: Vec<Element>,
+    /// The branches and loop bodies removed because they never run, in program order.
+    pub unreachable: Vec<Element>
+}
+
+/// Remove assignments whose value is never read and branches that never run.
+///
+/// The program is analysed backwards, following the structure of sequences, `if`s and
+/// `while`s: an assignment is dead if the variable is overwritten before it is read again and
+/// is not in `live_out`, the variables still read from the environment once the program is
+/// done. Other elements are left as they are, and are assumed to read every variable they
+/// mention. Anything that may stop the program, a failing assertion or operation, a `throw` or
+/// a `read`, reads the variables of `live_out` as well.
+///
+/// Only assignments of values that cannot fail are removed, i.e. of variables and literals. Run
+/// `simplify` first to fold constant expressions. A condition is known if `simplify` reduces it
+/// to a boolean.
+pub fn eliminate_dead_code(element: &Element, live_out: &HashSet<String>) -> Elimination {
+    let mut elimination = Elimination {
+        program: Element::DoNothing,
+        dead_stores: Vec::new(),
+        unreachable: Vec::new()
+    };
+    let (program, _) = eliminate(element, live_out, None, live_out, &mut elimination);
+    elimination.program = program;
+    elimination.dead_stores.reverse();
+    elimination.unreachable.reverse();
+    elimination
+}
+
+/// Eliminate dead code in an element, given the variables live after it, at the head of the
+/// innermost loop and wherever the program stops. Returns the new element and the variables
+/// live before it.
+fn eliminate(element: &Element, live: &HashSet<String>, loop_head: Option<&HashSet<String>>,
+             live_out: &HashSet<String>, removed: &mut Elimination) -> (Element, HashSet<String>) {
+    match *element {
+        Element::Number(_) | Element::Boolean(_) | Element::DoNothing => (element.clone(), live.clone()),
+        Element::Assign(ref name, ref val) => {
+            if !live.contains(name) && cannot_fail(val) {
+                removed.dead_stores.push(element.clone());
+                return (Element::DoNothing, live.clone());
+            }
+            let mut live_in = live.clone();
+            live_in.remove(name);
+            mentioned(val, &mut live_in);
+            if !cannot_fail(val) {
+                live_in.extend(live_out.iter().cloned());
+            }
+            (element.clone(), live_in)
+        },
+        Element::Sequence(ref first, ref second) => {
+            let (second, live_between) = eliminate(second, live, loop_head, live_out, removed);
+            let (first, live_in) = eliminate(first, &live_between, loop_head, live_out, removed);
+            (sequence(first, second), live_in)
+        },
+        Element::IfElse(ref cond, ref cons, ref alt) => {
+            match simplify(cond) {
+                Element::Boolean(taken) => {
+                    let (taken, never) = if taken { (cons, alt) } else { (alt, cons) };
+                    if **never != Element::DoNothing {
+                        removed.unreachable.push(*never.clone());
+                    }
+                    eliminate(taken, live, loop_head, live_out, removed)
+                },
+                _ => {
+                    // Backwards, like everything else, so the report ends up in program order.
+                    let (alt, mut live_in) = eliminate(alt, live, loop_head, live_out, removed);
+                    let (cons, live_cons) = eliminate(cons, live, loop_head, live_out, removed);
+                    live_in.extend(live_cons.into_iter());
+                    mentioned(cond, &mut live_in);
+                    if !cannot_fail(cond) {
+                        live_in.extend(live_out.iter().cloned());
+                    }
+                    (Element::IfElse(cond.clone(), box cons, box alt), live_in)
+                }
+            }
+        },
+        Element::While(ref cond, ref body, ref invariant) => {
+            if invariant.is_none() && simplify(cond) == Element::Boolean(false) {
+                if **body != Element::DoNothing {
+                    removed.unreachable.push(*body.clone());
+                }
+                return (Element::DoNothing, live.clone());
+            }
+
+            // Everything live after the loop or read by the condition is live at its head,
+            // and so is everything the body reads before writing it.
+            let mut base = live.clone();
+            mentioned(cond, &mut base);
+            if let Some(ref invariant) = *invariant {
+                mentioned(invariant, &mut base);
+            }
+            if !cannot_fail(cond) || invariant.is_some() {
+                base.extend(live_out.iter().cloned());
+            }
+            let mut head = base.clone();
+            loop {
+                let mut scratch = Elimination {
+                    program: Element::DoNothing,
+                    dead_stores: Vec::new(),
+                    unreachable: Vec::new()
+                };
+                let (_, live_body) = eliminate(body, &head, Some(&head), live_out, &mut scratch);
+                let mut next = base.clone();
+                next.extend(live_body.into_iter());
+                if next == head {
+                    break;
+                }
+                head = next;
+            }
+
+            let (body, _) = eliminate(body, &head, Some(&head), live_out, removed);
+            (Element::While(cond.clone(), box body, invariant.clone()), head)
+        },
+        _ => {
+            // `break` and `continue` jump to the end or the head of the loop, everything live
+            // at its head is a superset of both. Anything else may stop the program.
+            let mut live_in = live.clone();
+            if let Some(head) = loop_head {
+                live_in.extend(head.iter().cloned());
+            }
+            if !cannot_fail(element) {
+                live_in.extend(live_out.iter().cloned());
+            }
+            mentioned(element, &mut live_in);
+            (element.clone(), live_in)
+        }
+    }
+}
+
+/// Join two elements in a sequence, dropping the `do-nothing`s left by removed code.
+fn sequence(first: Element, second: Element) -> Element {
+    match (first, second) {
+        (Element::DoNothing, second) => second,
+        (first, Element::DoNothing) if results_in_nothing(&first) => first,
+        (first, second) => Element::Sequence(box first, box second)
+    }
+}
+
+/// Wether the element reduces to `do-nothing` whenever it completes normally.
+fn results_in_nothing(element: &Element) -> bool {
+    match *element {
+        Element::DoNothing | Element::Assign(..) | Element::While(..) => true,
+        Element::Sequence(_, ref second) => results_in_nothing(second),
+        Element::IfElse(_, ref cons, ref alt) => results_in_nothing(cons) && results_in_nothing(alt),
+        _ => false
+    }
+}
+
+/// Wether reducing the element can never throw, or have any other effect.
+fn cannot_fail(element: &Element) -> bool {
+    match *element {
+        Element::Number(_) | Element::Boolean(_) | Element::DoNothing | Element::Variable(_) => true,
+        _ => false
+    }
+}
+
+/// Add every variable the element mentions to the set.
+fn mentioned(element: &Element, variables: &mut HashSet<String>) {
+    match *element {
+        Element::Variable(ref name) | Element::AssignIndex(ref name, _, _) |
+        Element::AssignField(ref name, _, _) => {
+            variables.insert(name.clone());
+        },
+        _ => {}
+    }
+    for child in element.children() {
+        mentioned(child, variables);
+    }
+}
+
+/// Hoist loop-invariant expressions out of `while` loops and reduce multiplications by
+/// induction variables to additions.
+///
+/// An addition, multiplication or comparison is invariant if none of its variables is assigned
+/// anywhere in the loop. A variable `i` is an induction variable if the only assignment to it in
+/// the loop is a statement `i = i + k` of the body, before anything that could `break` or
+/// `continue`, with `k` a number. Such expressions, and multiplications `i * c` with `c` a number,
+/// are bound to temporaries with `let` around the loop if every iteration evaluates them right
+/// after the condition, before any store or anything that could fail or be observed. A
+/// temporary for `i * c` grows by `k * c` right after `i` grows by `k`.
+///
+/// The loop is only entered if its condition holds, and the temporaries are gone once it is
+/// done. Loops with an `assert` or `read`, which could stop the machine with the temporaries
+/// still set, are left alone, as are loops with invariants or conditions that have side effects.
+/// A temporary may overflow after the last iteration, where the multiplication it replaces would
+/// not be evaluated anymore.
+pub fn optimize_loops(element: &Element) -> Element {
+    let mut used = HashSet::new();
+    mentioned(element, &mut used);
+    let mut writes = Vec::new();
+    element.written(&mut writes);
+    used.extend(writes.into_iter());
+
+    let mut temporaries = Temporaries { used: used, next: 0 };
+    loops(element, &mut temporaries)
+}
+
+/// Hands out variable names for temporaries.
+struct Temporaries {
+    used: HashSet<String>,
+    next: usize
+}
+
+impl Temporaries {
+    /// A name not used anywhere in the program yet.
+    fn fresh(&mut self) -> String {
+        loop {
+            let name = format!("t{}", self.next);
+            self.next += 1;
+            if self.used.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+}
+
+/// Optimize the loops in an element, inner loops first.
+fn loops(element: &Element, temporaries: &mut Temporaries) -> Element {
+    match *element {
+        Element::Sequence(ref first, ref second) => {
+            let first = loops(first, temporaries);
+            Element::Sequence(box first, box loops(second, temporaries))
+        },
+        Element::IfElse(ref cond, ref cons, ref alt) => {
+            let cons = loops(cons, temporaries);
+            Element::IfElse(cond.clone(), box cons, box loops(alt, temporaries))
+        },
+        Element::While(ref cond, ref body, None) if invariant(cond, &HashSet::new()) && !may_stop(body) => {
+            let body = loops(body, temporaries);
+            optimize_loop(cond, body, temporaries)
+        },
+        _ => element.clone()
+    }
+}
+
+fn optimize_loop(cond: &Element, body: Element, temporaries: &mut Temporaries) -> Element {
+    let mut writes = Vec::new();
+    cond.written(&mut writes);
+    body.written(&mut writes);
+    let assigned: HashSet<String> = writes.iter().cloned().collect();
+
+    let mut statements = Vec::new();
+    top_level(&body, &mut statements);
+
+    // (induction variable, step)
+    let mut inductions = Vec::new();
+    for statement in statements.iter().take_while(|s| !s.may_jump()) {
+        match **statement {
+            Element::Assign(ref i, box Element::Add(box Element::Variable(ref v), box Element::Number(k))) |
+            Element::Assign(ref i, box Element::Add(box Element::Number(k), box Element::Variable(ref v)))
+                if i == v && writes.iter().filter(|w| *w == i).count() == 1 => inductions.push((i.clone(), k)),
+            _ => {}
+        }
+    }
+
+    // The condition is checked once before the temporaries are bound, after that only the start
+    // of the first statement is evaluated before anything is stored.
+    let mut leading = Vec::new();
+    evaluated_first(cond, &assigned, &inductions, &mut leading);
+    match statements.first().map(|s| &**s) {
+        Some(&Element::Assign(_, ref e)) | Some(&Element::Print(ref e)) |
+        Some(&Element::IfElse(ref e, _, _)) | Some(&Element::While(ref e, _, _)) => {
+            evaluated_first(e, &assigned, &inductions, &mut leading);
+        },
+        _ => {}
+    }
+
+    if leading.is_empty() {
+        return Element::While(box cond.clone(), box body, None);
+    }
+
+    let bound: Vec<(Element, String)> = leading.into_iter().map(|e| (e, temporaries.fresh())).collect();
+    let replacements: Vec<(Element, Element)> = bound.iter()
+        .map(|&(ref e, ref t)| (e.clone(), Element::Variable(t.clone()))).collect();
+
+    // (induction variable, step of the temporary, temporary)
+    let mut reductions = Vec::new();
+    for &(ref e, ref t) in bound.iter() {
+        if let Some((i, step)) = reduction(e, &inductions) {
+            reductions.push((i, step, t.clone()));
+        }
+    }
+
+    let body = grow_temporaries(&replace(&body, &replacements), &reductions);
+    let mut optimized = Element::While(box replace(cond, &replacements), box body, None);
+    for (n, &(ref e, ref t)) in bound.iter().enumerate().rev() {
+        // Reusing the temporaries bound around it.
+        let e = replace(e, &replacements[..n].to_vec());
+        optimized = Element::Let(t.clone(), box e, box optimized);
+    }
+    Element::IfElse(box cond.clone(), box optimized, box Element::DoNothing)
+}
+
+/// Add the largest invariant subexpressions and the multiplications of induction variables of an
+/// expression to the list, in the order they are evaluated in, up to the first operation that
+/// may fail. Returns wether there is none.
+fn evaluated_first(element: &Element, assigned: &HashSet<String>, inductions: &Vec<(String, i64)>,
+                   found: &mut Vec<Element>) -> bool {
+    match *element {
+        Element::Number(_) | Element::Boolean(_) | Element::Variable(_) => true,
+        Element::Add(ref l, ref r) | Element::Multiply(ref l, ref r) | Element::LessThan(ref l, ref r) => {
+            let mut variables = HashSet::new();
+            mentioned(element, &mut variables);
+
+            if invariant(element, assigned) && variables.is_empty() {
+                // Left to `simplify`, unless it would overflow.
+                return simplify(element).number().is_some();
+            }
+            if invariant(element, assigned) || reduction(element, inductions).is_some() {
+                if !found.contains(element) {
+                    found.push(element.clone());
+                }
+                return true;
+            }
+
+            if evaluated_first(l, assigned, inductions, found) {
+                evaluated_first(r, assigned, inductions, found);
+            }
+            // The operation itself may fail.
+            false
+        },
+        _ => false
+    }
+}
+
+/// The induction variable and the step of the temporary, if the element is a multiplication of
+/// an induction variable with a number.
+fn reduction(element: &Element, inductions: &Vec<(String, i64)>) -> Option<(String, i64)> {
+    let (v, c) = match *element {
+        Element::Multiply(box Element::Variable(ref v), box Element::Number(c)) |
+        Element::Multiply(box Element::Number(c), box Element::Variable(ref v)) => (v, c),
+        _ => return None
+    };
+    inductions.iter()
+        .find(|&&(ref i, _)| i == v)
+        .and_then(|&(ref i, k)| k.checked_mul(c).map(|step| (i.clone(), step)))
+}
+
+/// Wether the element could stop the machine in the middle of it, at a failing `assert` or a
+/// `read` without input.
+fn may_stop(element: &Element) -> bool {
+    match *element {
+        Element::Assert(..) | Element::Read(_) => true,
+        _ => element.children().into_iter().any(may_stop)
+    }
+}
+
+/// Wether the element is an expression free of side effects, reading none of the assigned
+/// variables.
+fn invariant(element: &Element, assigned: &HashSet<String>) -> bool {
+    match *element {
+        Element::Number(_) | Element::Boolean(_) => true,
+        Element::Variable(ref name) => !assigned.contains(name),
+        Element::Add(ref l, ref r) | Element::Multiply(ref l, ref r) | Element::LessThan(ref l, ref r) => {
+            invariant(l, assigned) && invariant(r, assigned)
+        },
+        _ => false
+    }
+}
+
+/// Replace every occurrence of the first element of a pair by the second.
+fn replace(element: &Element, replacements: &Vec<(Element, Element)>) -> Element {
+    match replacements.iter().find(|&&(ref from, _)| from == element) {
+        Some(&(_, ref to)) => to.clone(),
+        None => element.map_children(|child| replace(child, replacements))
+    }
+}
+
+/// Follow each assignment to an induction variable by growing its temporaries.
+fn grow_temporaries(element: &Element, reductions: &Vec<(String, i64, String)>) -> Element {
+    match *element {
+        Element::Sequence(ref first, ref second) => {
+            Element::Sequence(box grow_temporaries(first, reductions), box grow_temporaries(second, reductions))
+        },
+        Element::Assign(ref name, _) => {
+            reductions.iter().filter(|&&(ref i, _, _)| i == name).fold(element.clone(), |grown, &(_, step, ref t)| {
+                Element::Sequence(box grown, assign!(t.clone(), add!(variable!(t.clone()), number!(step))))
+            })
+        },
+        _ => element.clone()
+    }
+}
+
+/// The statements of a sequence, without the sequences themselves.
+fn top_level<'a>(element: &'a Element, statements: &mut Vec<&'a Element>) {
+    match *element {
+        Element::Sequence(ref first, ref second) => {
+            top_level(first, statements);
+            top_level(second, statements);
+        },
+        _ => statements.push(element)
+    }
+}
+
+/// Wether the element results in a number whenever it completes normally.
+fn yields_number(element: &Element) -> bool {
+    match *element {
+        Element::Number(_) | Element::Add(..) | Element::Multiply(..) => true,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+fn assert_same_result(program: Box<Element>, env: ::std::collections::hash_map::HashMap<String, Box<Element>>) {
+    use Machine;
+
+    let mut original = Machine::new(program.clone(), env.clone());
+    let mut simplified = Machine::new(box simplify(&program), env);
+    assert_eq!(original.run(), simplified.run());
+    assert_eq!(original.expression(), simplified.expression());
+    assert_eq!(original.clone_env(), simplified.clone_env());
+    assert_eq!(original.output(), simplified.output());
+}
+
+#[test]
+fn test_simplify_folds_constants() {
+    assert_eq!(number!(14), box simplify(&multiply!(add!(number!(3), number!(4)), number!(2))));
+    assert_eq!(boolean!(true), box simplify(&less_than!(add!(number!(1), number!(1)), number!(3))));
+
+    // An overflow has to happen at runtime.
+    let overflowing = add!(number!(::std::i64::MAX), number!(1));
+    assert_eq!(overflowing, box simplify(&overflowing));
+}
+
+#[test]
+fn test_simplify_applies_identities() {
+    assert_eq!(
+        add!(variable!("x"), variable!("y")),
+        box simplify(&multiply!(add!(variable!("x"), variable!("y")), number!(1))));
+    assert_eq!(
+        multiply!(variable!("x"), number!(2)),
+        box simplify(&add!(number!(0), multiply!(variable!("x"), add!(number!(1), number!(1))))));
+
+    // `x` may not be a number at all.
+    let unknown = multiply!(variable!("x"), number!(1));
+    assert_eq!(unknown, box simplify(&unknown));
+    let unknown = multiply!(variable!("x"), number!(0));
+    assert_eq!(unknown, box simplify(&unknown));
+}
+
+#[test]
+fn test_simplify_removes_dead_branches() {
+    assert_eq!(
+        assign!("y", number!(2)),
+        box simplify(&sequence!(
+            if_!(less_than!(number!(2), number!(1)), assign!("y", number!(1))),
+            ifelse!(boolean!(true), assign!("y", number!(2)), assign!("y", number!(3)))
+            )));
+}
+
+#[test]
+fn test_simplify_preserves_semantics() {
+    use std::collections::hash_map::HashMap;
+
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+    assert_same_result(
+        while_!(
+            less_than!(variable!("x"), add!(number!(2), number!(3))),
+            sequence!(
+                if_!(boolean!(false), print_!(variable!("x"))),
+                assign!("x", multiply!(multiply!(variable!("x"), number!(3)), number!(1)))
+                )
+            ),
+        env);
+
+    // Still fails just like the original.
+    assert_same_result(multiply!(variable!("unset"), number!(1)), HashMap::new());
+    assert_same_result(multiply!(add!(boolean!(true), number!(0)), number!(0)), HashMap::new());
+}
+
+#[cfg(test)]
+fn live(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+#[test]
+fn test_eliminate_overwritten_stores() {
+    let elimination = eliminate_dead_code(
+        &sequence!(
+            assign!("x", number!(1)),
+            sequence!(
+                assign!("t", variable!("x")),
+                sequence!(assign!("x", number!(2)), assign!("y", variable!("x")))
+                )
+            ),
+        &live(&["y"]));
+
+    assert_eq!(
+        sequence!(assign!("x", number!(2)), assign!("y", variable!("x"))),
+        box elimination.program);
+    assert_eq!(
+        vec![*assign!("x", number!(1)), *assign!("t", variable!("x"))],
+        elimination.dead_stores);
+    assert!(elimination.unreachable.is_empty());
+
+    // The result of a failing computation is kept, the failure is observable.
+    let program = sequence!(assign!("x", add!(variable!("y"), number!(1))), assign!("x", number!(2)));
+    assert_eq!(program, box eliminate_dead_code(&program, &live(&["x"])).program);
+
+    // The environment is observable where the program stops, too.
+    let program = sequence!(
+        assign!("x", number!(1)),
+        sequence!(assert_!(boolean!(false), "stop"), assign!("x", number!(2)))
+        );
+    assert_eq!(program, box eliminate_dead_code(&program, &live(&["x"])).program);
+    let program = sequence!(
+        assign!("x", number!(1)),
+        sequence!(assign!("y", add!(variable!("a"), number!(1))), assign!("x", number!(2)))
+        );
+    assert_eq!(program, box eliminate_dead_code(&program, &live(&["x"])).program);
+
+    // Unless the variable isn't observed at all.
+    let elimination = eliminate_dead_code(&program, &live(&["y"]));
+    assert_eq!(vec![*assign!("x", number!(1)), *assign!("x", number!(2))], elimination.dead_stores);
+}
+
+#[test]
+fn test_eliminate_unreachable_branches() {
+    let elimination = eliminate_dead_code(
+        &sequence!(
+            ifelse!(less_than!(number!(1), number!(2)), assign!("x", number!(1)), assign!("y", number!(2))),
+            while_!(boolean!(false), assign!("x", number!(3)))
+            ),
+        &live(&["x", "y"]));
+
+    assert_eq!(assign!("x", number!(1)), box elimination.program);
+    assert_eq!(vec![*assign!("y", number!(2)), *assign!("x", number!(3))], elimination.unreachable);
+}
+
+#[test]
+fn test_eliminate_in_loops() {
+    use std::collections::hash_map::HashMap;
+    use Machine;
+
+    // `t` is never read, `i` is read by the condition of the next iteration.
+    let program = sequence!(
+        assign!("i", number!(0)),
+        while_!(
+            less_than!(variable!("i"), number!(3)),
+            sequence!(assign!("t", variable!("i")), assign!("i", add!(variable!("i"), number!(1))))
+            )
+        );
+    let elimination = eliminate_dead_code(&program, &live(&["i"]));
+    assert_eq!(vec![*assign!("t", variable!("i"))], elimination.dead_stores);
+
+    let mut m = Machine::new_with_empty_env(box elimination.program);
+    m.run();
+    assert_eq!(Some(&number!(3)), m.clone_env().get("i"));
+
+    // `y = x` is live when leaving the loop through `break`, even though `y = 0` follows.
+    let program = while_!(
+        less_than!(variable!("x"), number!(10)),
+        sequence!(
+            assign!("y", variable!("x")),
+            sequence!(
+                if_!(less_than!(number!(5), variable!("x")), break_!()),
+                sequence!(assign!("y", number!(0)), assign!("x", add!(variable!("x"), number!(1))))
+                )
+            )
+        );
+    let elimination = eliminate_dead_code(&program, &live(&["y"]));
+    assert!(elimination.dead_stores.is_empty());
+
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(0));
+    let mut original = Machine::new(program, env.clone());
+    let mut optimized = Machine::new(box elimination.program, env);
+    original.run();
+    optimized.run();
+    assert_eq!(Some(&number!(6)), optimized.clone_env().get("y"));
+    assert_eq!(original.clone_env(), optimized.clone_env());
+}
+
+#[cfg(test)]
+fn steps(program: Box<Element>, env: ::std::collections::hash_map::HashMap<String, Box<Element>>)
+         -> (usize, ::Outcome, ::std::collections::hash_map::HashMap<String, Box<Element>>, Vec<Box<Element>>) {
+    use Machine;
+
+    let mut m = Machine::new(program, env);
+    let mut steps = 0;
+    while m.expression().is_reducible() {
+        m.step();
+        steps += 1;
+    }
+    let outcome = m.run();
+    (steps, outcome, m.clone_env(), m.output().to_vec())
+}
+
+/// Run a program and its optimized version on the machine and compare how they end.
+#[cfg(test)]
+fn assert_same_run(program: &Box<Element>, optimized: &Box<Element>,
+                   env: ::std::collections::hash_map::HashMap<String, Box<Element>>) {
+    let (_, original_outcome, original_env, original_output) = steps(program.clone(), env.clone());
+    let (_, optimized_outcome, optimized_env, optimized_output) = steps(optimized.clone(), env);
+    assert_eq!(original_outcome, optimized_outcome);
+    assert_eq!(original_env, optimized_env);
+    assert_eq!(original_output, optimized_output);
+}
+
+#[test]
+fn test_optimize_loops() {
+    let program = sequence!(
+        assign!("i", number!(0)),
+        while_!(
+            less_than!(variable!("i"), multiply!(variable!("n"), variable!("n"))),
+            sequence!(
+                assign!("sum", add!(variable!("sum"), add!(multiply!(variable!("i"), number!(4)), variable!("k")))),
+                assign!("i", add!(variable!("i"), number!(2)))
+                )
+            )
+        );
+
+    assert_eq!(
+        sequence!(
+            assign!("i", number!(0)),
+            if_!(
+                less_than!(variable!("i"), multiply!(variable!("n"), variable!("n"))),
+                let_!("t0", multiply!(variable!("n"), variable!("n")),
+                    let_!("t1", multiply!(variable!("i"), number!(4)),
+                        while_!(
+                            less_than!(variable!("i"), variable!("t0")),
+                            sequence!(
+                                assign!("sum", add!(variable!("sum"), add!(variable!("t1"), variable!("k")))),
+                                sequence!(
+                                    assign!("i", add!(variable!("i"), number!(2))),
+                                    assign!("t1", add!(variable!("t1"), number!(8)))
+                                    )
+                                )
+                            )
+                        )
+                    )
+                )
+            ),
+        box optimize_loops(&program));
+}
+
+#[test]
+fn test_hoisting_saves_steps() {
+    use std::collections::hash_map::HashMap;
+
+    let program = while_!(
+        less_than!(variable!("i"), multiply!(variable!("n"), variable!("n"))),
+        assign!("i", add!(variable!("i"), number!(1)))
+        );
+
+    // `t0` is only in the environment, the temporary must not overwrite it.
+    let mut env = HashMap::new();
+    env.insert("i".to_string(), number!(0));
+    env.insert("n".to_string(), number!(3));
+    env.insert("t0".to_string(), number!(7));
+    let (original_steps, _, original_env, _) = steps(program.clone(), env.clone());
+    let (optimized_steps, _, optimized_env, _) = steps(box optimize_loops(&program), env);
+    assert_eq!(original_env, optimized_env);
+    assert!(optimized_steps < original_steps);
+}
+
+#[test]
+fn test_strength_reduction_agrees_with_machine() {
+    use std::collections::hash_map::HashMap;
+
+    //  i = 0; while (i < n) [ sum = sum + 3 * i; print i * 3; i = 2 + i ]
+    let program = sequence!(
+        assign!("i", number!(0)),
+        while_!(
+            less_than!(variable!("i"), variable!("n")),
+            sequence!(
+                assign!("sum", add!(variable!("sum"), multiply!(number!(3), variable!("i")))),
+                sequence!(
+                    print_!(multiply!(variable!("i"), number!(3))),
+                    assign!("i", add!(number!(2), variable!("i")))
+                    )
+                )
+            )
+        );
+    let optimized = box optimize_loops(&program);
+    assert!(format!("{:?}", optimized).contains("t0 = t0 + 6"));
+
+    let mut inputs: Vec<Box<Element>> = (0..6).map(|n| number!(n)).collect();
+    inputs.push(boolean!(true));
+    inputs.push(list!());
+    for n in inputs.into_iter() {
+        let mut env = HashMap::new();
+        env.insert("n".to_string(), n);
+        env.insert("sum".to_string(), number!(0));
+        assert_same_run(&program, &optimized, env);
+    }
+}
+
+#[test]
+fn test_optimize_loops_only_hoists_what_is_evaluated_first() {
+    use std::collections::hash_map::HashMap;
+
+    // `y * y` is not evaluated at all if `c` is false.
+    let program = while_!(
+        less_than!(variable!("i"), number!(3)),
+        sequence!(
+            if_!(variable!("c"), assign!("x", multiply!(variable!("y"), variable!("y")))),
+            assign!("i", add!(variable!("i"), number!(1)))
+            )
+        );
+    assert_eq!(program, box optimize_loops(&program));
+
+    // Something is printed before `y * y` may fail.
+    let program = while_!(
+        less_than!(variable!("i"), number!(3)),
+        sequence!(
+            print_!(variable!("i")),
+            sequence!(
+                assign!("x", multiply!(variable!("y"), variable!("y"))),
+                assign!("i", add!(variable!("i"), number!(1)))
+                )
+            )
+        );
+    assert_eq!(program, box optimize_loops(&program));
+
+    // `i * 2` may fail before `y * y`.
+    let program = while_!(
+        less_than!(variable!("i"), number!(3)),
+        assign!("i", add!(multiply!(variable!("i"), number!(2)), multiply!(variable!("y"), variable!("y"))))
+        );
+    assert_eq!(program, box optimize_loops(&program));
+
+    // `x` is set before `n * n` fails.
+    let program = while_!(
+        less_than!(variable!("i"), number!(3)),
+        sequence!(
+            assign!("x", variable!("i")),
+            sequence!(
+                assign!("y", multiply!(variable!("n"), variable!("n"))),
+                assign!("i", add!(variable!("i"), number!(1)))
+                )
+            )
+        );
+    let optimized = box optimize_loops(&program);
+    assert_eq!(program, optimized);
+
+    let mut env = HashMap::new();
+    env.insert("i".to_string(), number!(0));
+    env.insert("n".to_string(), list!());
+    assert_same_run(&program, &optimized, env);
+}
+
+#[test]
+fn test_optimize_loops_agrees_with_machine() {
+    use std::collections::hash_map::HashMap;
+
+    // Nested loops summing up `n * n + i * 3 + j` for every pair below `n`, printing `i`.
+    let program = sequence!(
+        assign!("i", number!(0)),
+        while_!(
+            less_than!(variable!("i"), variable!("n")),
+            sequence!(
+                assign!("j", number!(0)),
+                sequence!(
+                    while_!(
+                        less_than!(variable!("j"), variable!("n")),
+                        sequence!(
+                            assign!("sum", add!(
+                                add!(variable!("sum"), add!(multiply!(variable!("n"), variable!("n")), multiply!(variable!("i"), number!(3)))),
+                                variable!("j"))),
+                            assign!("j", add!(number!(1), variable!("j")))
+                            )
+                        ),
+                    sequence!(print_!(variable!("i")), assign!("i", add!(variable!("i"), number!(1))))
+                    )
+                )
+            )
+        );
+    let optimized = box optimize_loops(&program);
+    assert!(optimized != program);
+
+    let mut inputs: Vec<Box<Element>> = (0..4).map(|n| number!(n)).collect();
+    inputs.push(boolean!(true));
+    inputs.push(list!());
+    for n in inputs.into_iter() {
+        let mut env = HashMap::new();
+        env.insert("n".to_string(), n);
+        env.insert("sum".to_string(), number!(0));
+        assert_same_run(&program, &optimized, env);
+    }
+}
@@ -0,0 +1,592 @@
+//! A register machine for SIMPLE, with a verifier and a text format for storing programs.
+//!
+//! Every variable of a program lives in its own register, numbered from zero in the order of
+//! `Program::variables`; intermediate values get registers of their own after those. A program
+//! is verified before it runs, so a malformed one, say loaded from a file somebody edited by
+//! hand, is rejected instead of indexing out of bounds.
+//!
+//! The text format has one directive or instruction per line:
+//!
+//! ```text
+//! registers 4
+//! variables x
+//! result r3
+//! const r1 5
+//! lt r2 r0 r1
+//! jump-if-false r2 5
+//! mul r0 r0 r1
+//! jump 0
+//! const r3 do-nothing
+//! ```
+//!
+//! Jump targets count instructions only, starting at zero.
+//!
+//! A program may use at most `MAX_REGISTERS` registers, and `Program::run` gives up after a given
+//! number of instructions, so not even a hostile file can exhaust memory or hang the machine.
+
+use std::collections::hash_map::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use Element;
+
+/// The most registers a program may use.
+pub const MAX_REGISTERS: usize = 1 << 16;
+
+/// A single instruction of the register machine.
+#[derive(Clone,PartialEq,Debug)]
+pub enum Instruction {
+    /// Put a number, boolean or `do-nothing` into a register.
+    Const(usize, Element),
+    /// Copy the second register into the first.
+    Move(usize, usize),
+    /// Store the sum of the last two registers into the first.
+    Add(usize, usize, usize),
+    /// Store the product of the last two registers into the first.
+    Multiply(usize, usize, usize),
+    /// Store wether the second register is less than the third into the first.
+    LessThan(usize, usize, usize),
+    /// Continue at the given instruction.
+    Jump(usize),
+    /// Continue at the given instruction if the register holds false.
+    JumpIfFalse(usize, usize)
+}
+
+/// A register machine program.
+#[derive(Clone,PartialEq,Debug)]
+pub struct Program {
+    /// The number of registers used.
+    pub registers: usize,
+    /// The variable kept in each of the first registers.
+    pub variables: Vec<String>,
+    /// The register holding the value of the whole program when it is done.
+    pub result: usize,
+    pub instructions: Vec<Instruction>
+}
+
+/// Why a program was rejected by the verifier.
+#[derive(Debug,PartialEq)]
+pub enum VerifyError {
+    /// The program uses more than `MAX_REGISTERS` registers.
+    TooManyRegisters(usize),
+    /// There are more variables than registers.
+    TooManyVariables,
+    /// A variable is given two registers.
+    DuplicateVariable(String),
+    /// The result register does not exist.
+    BadResult(usize),
+    /// The instruction at the given address uses a register that does not exist.
+    BadRegister { at: usize, register: usize },
+    /// The instruction at the given address jumps past the end of the program.
+    JumpOutOfRange { at: usize, target: usize },
+    /// The instruction at the given address loads something that is not a constant.
+    BadConstant { at: usize }
+}
+
+/// Why a program could not be loaded.
+#[derive(Debug,PartialEq)]
+pub enum LoadError {
+    /// The file could not be read.
+    Io(String),
+    /// The line with the given number, counting from one, could not be parsed.
+    Parse { line: usize, message: String },
+    /// The program was parsed, but is malformed.
+    Verify(VerifyError)
+}
+
+/// Lower an element to a register machine program.
+pub fn compile(element: &Element) -> Result<Program, String> {
+    let mut variables = Vec::new();
+    collect_variables(element, &mut variables);
+
+    let mut program = Program {
+        registers: variables.len(),
+        variables: variables,
+        result: 0,
+        instructions: Vec::new()
+    };
+    program.result = try!(program.emit(element));
+    Ok(program)
+}
+
+/// Every variable read or assigned, in order of first appearance.
+fn collect_variables(element: &Element, variables: &mut Vec<String>) {
+    let add = |name: &String, variables: &mut Vec<String>| {
+        if !variables.contains(name) {
+            variables.push(name.clone());
+        }
+    };
+
+    match *element {
+        Element::Variable(ref name) => add(name, variables),
+        Element::Assign(ref name, ref val) => {
+            add(name, variables);
+            collect_variables(val, variables);
+        },
+        Element::Add(ref l, ref r) | Element::Multiply(ref l, ref r) |
+        Element::LessThan(ref l, ref r) | Element::Sequence(ref l, ref r) |
+        Element::While(ref l, ref r, _) => {
+            collect_variables(l, variables);
+            collect_variables(r, variables);
+        },
+        Element::IfElse(ref cond, ref cons, ref alt) => {
+            collect_variables(cond, variables);
+            collect_variables(cons, variables);
+            collect_variables(alt, variables);
+        },
+        _ => {}
+    }
+}
+
+impl Program {
+    /// A fresh register for an intermediate value.
+    fn temporary(&mut self) -> usize {
+        self.registers += 1;
+        self.registers - 1
+    }
+
+    fn register_of(&self, name: &String) -> usize {
+        self.variables.iter().position(|v| v == name).unwrap()
+    }
+
+    /// Append the instructions for an element, returning the register that holds its value.
+    fn emit(&mut self, element: &Element) -> Result<usize, String> {
+        match *element {
+            Element::Number(_) | Element::Boolean(_) | Element::DoNothing => {
+                let dst = self.temporary();
+                self.instructions.push(Instruction::Const(dst, element.clone()));
+                Ok(dst)
+            },
+            Element::Add(ref l, ref r) | Element::Multiply(ref l, ref r) |
+            Element::LessThan(ref l, ref r) => {
+                let l = try!(self.emit(l));
+                let r = try!(self.emit(r));
+                let dst = self.temporary();
+                self.instructions.push(match *element {
+                    Element::Add(..) => Instruction::Add(dst, l, r),
+                    Element::Multiply(..) => Instruction::Multiply(dst, l, r),
+                    _ => Instruction::LessThan(dst, l, r)
+                });
+                Ok(dst)
+            },
+            Element::Variable(ref name) => {
+                // Copied, so that assignments later in the same expression don't change it.
+                let src = self.register_of(name);
+                let dst = self.temporary();
+                self.instructions.push(Instruction::Move(dst, src));
+                Ok(dst)
+            },
+            Element::Assign(ref name, ref val) => {
+                let src = try!(self.emit(val));
+                let dst = self.register_of(name);
+                self.instructions.push(Instruction::Move(dst, src));
+                self.emit(&Element::DoNothing)
+            },
+            Element::Sequence(ref first, ref second) => {
+                try!(self.emit(first));
+                self.emit(second)
+            },
+            Element::IfElse(ref cond, ref cons, ref alt) => {
+                let dst = self.temporary();
+                let cond = try!(self.emit(cond));
+                let to_alt = self.placeholder();
+                let cons = try!(self.emit(cons));
+                self.instructions.push(Instruction::Move(dst, cons));
+                let to_end = self.placeholder();
+                self.instructions[to_alt] = Instruction::JumpIfFalse(cond, self.instructions.len());
+                let alt = try!(self.emit(alt));
+                self.instructions.push(Instruction::Move(dst, alt));
+                self.instructions[to_end] = Instruction::Jump(self.instructions.len());
+                Ok(dst)
+            },
+            Element::While(ref cond, ref body, None) => {
+                let start = self.instructions.len();
+                let cond = try!(self.emit(cond));
+                let to_end = self.placeholder();
+                try!(self.emit(body));
+                self.instructions.push(Instruction::Jump(start));
+                self.instructions[to_end] = Instruction::JumpIfFalse(cond, self.instructions.len());
+                self.emit(&Element::DoNothing)
+            },
+            _ => Err(format!("cannot compile {:?}", element))
+        }
+    }
+
+    /// Reserve room for a jump whose target is not known yet.
+    fn placeholder(&mut self) -> usize {
+        self.instructions.push(Instruction::Jump(0));
+        self.instructions.len() - 1
+    }
+
+    /// Check that the program is well-formed, so running it cannot go wrong.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        if self.registers > MAX_REGISTERS {
+            return Err(VerifyError::TooManyRegisters(self.registers));
+        }
+        if self.variables.len() > self.registers {
+            return Err(VerifyError::TooManyVariables);
+        }
+        for (i, name) in self.variables.iter().enumerate() {
+            if self.variables[..i].contains(name) {
+                return Err(VerifyError::DuplicateVariable(name.clone()));
+            }
+        }
+        if self.result >= self.registers {
+            return Err(VerifyError::BadResult(self.result));
+        }
+
+        for (at, instruction) in self.instructions.iter().enumerate() {
+            let (registers, target) = match *instruction {
+                Instruction::Const(dst, ref val) => {
+                    match *val {
+                        Element::Number(_) | Element::Boolean(_) | Element::DoNothing => {},
+                        _ => return Err(VerifyError::BadConstant { at: at })
+                    }
+                    (vec![dst], None)
+                },
+                Instruction::Move(dst, src) => (vec![dst, src], None),
+                Instruction::Add(dst, l, r) | Instruction::Multiply(dst, l, r) |
+                Instruction::LessThan(dst, l, r) => (vec![dst, l, r], None),
+                Instruction::Jump(target) => (vec![], Some(target)),
+                Instruction::JumpIfFalse(cond, target) => (vec![cond], Some(target))
+            };
+
+            for &register in registers.iter() {
+                if register >= self.registers {
+                    return Err(VerifyError::BadRegister { at: at, register: register });
+                }
+            }
+            if let Some(target) = target {
+                // Jumping right past the last instruction ends the program.
+                if target > self.instructions.len() {
+                    return Err(VerifyError::JumpOutOfRange { at: at, target: target });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verify and run the program, returning its value. Fails once more than `max_steps`
+    /// instructions were run.
+    ///
+    /// Variables are read from and written back to the environment; only those set before or
+    /// assigned while running end up in it.
+    pub fn run(&self, environment: &mut HashMap<String, Box<Element>>, max_steps: usize) -> Result<Element, String> {
+        if let Err(e) = self.verify() {
+            return Err(format!("malformed program: {:?}", e));
+        }
+
+        let mut registers = vec![Element::DoNothing; self.registers];
+        let mut bound = vec![false; self.variables.len()];
+        for (i, name) in self.variables.iter().enumerate() {
+            if let Some(val) = environment.get(name) {
+                registers[i] = *val.clone();
+                bound[i] = true;
+            }
+        }
+
+        let mut pc = 0;
+        let mut steps = 0;
+        while pc < self.instructions.len() {
+            if steps == max_steps {
+                return Err(format!("gave up after {} steps", max_steps));
+            }
+            steps += 1;
+
+            let instruction = &self.instructions[pc];
+            pc += 1;
+
+            match *instruction {
+                Instruction::Const(dst, ref val) => registers[dst] = val.clone(),
+                Instruction::Move(dst, src) => {
+                    registers[dst] = registers[src].clone();
+                    if dst < bound.len() {
+                        bound[dst] = true;
+                    }
+                },
+                Instruction::Add(dst, l, r) | Instruction::Multiply(dst, l, r) |
+                Instruction::LessThan(dst, l, r) => {
+                    let (l, r) = match (number(&registers[l]), number(&registers[r])) {
+                        (Some(l), Some(r)) => (l, r),
+                        _ => return Err(format!("type mismatch at {:04}", pc - 1))
+                    };
+                    let result = match *instruction {
+                        Instruction::Add(..) => l.checked_add(r).map(Element::Number),
+                        Instruction::Multiply(..) => l.checked_mul(r).map(Element::Number),
+                        _ => Some(Element::Boolean(l < r))
+                    };
+                    match result {
+                        Some(val) => registers[dst] = val,
+                        None => return Err(format!("overflow at {:04}", pc - 1))
+                    }
+                },
+                Instruction::Jump(target) => pc = target,
+                Instruction::JumpIfFalse(cond, target) => {
+                    match registers[cond] {
+                        Element::Boolean(true) => {},
+                        Element::Boolean(false) => pc = target,
+                        _ => return Err(format!("type mismatch at {:04}", pc - 1))
+                    }
+                }
+            }
+        }
+
+        for (i, name) in self.variables.iter().enumerate() {
+            if bound[i] {
+                environment.insert(name.clone(), box registers[i].clone());
+            }
+        }
+        Ok(registers[self.result].clone())
+    }
+
+    /// The program in the text format.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("registers {}\n", self.registers);
+        if !self.variables.is_empty() {
+            text.push_str(&format!("variables {}\n", self.variables.join(" ")));
+        }
+        text.push_str(&format!("result r{}\n", self.result));
+
+        for instruction in self.instructions.iter() {
+            let line = match *instruction {
+                Instruction::Const(dst, ref val) => format!("const r{} {:?}", dst, val),
+                Instruction::Move(dst, src) => format!("move r{} r{}", dst, src),
+                Instruction::Add(dst, l, r) => format!("add r{} r{} r{}", dst, l, r),
+                Instruction::Multiply(dst, l, r) => format!("mul r{} r{} r{}", dst, l, r),
+                Instruction::LessThan(dst, l, r) => format!("lt r{} r{} r{}", dst, l, r),
+                Instruction::Jump(target) => format!("jump {}", target),
+                Instruction::JumpIfFalse(cond, target) => format!("jump-if-false r{} {}", cond, target)
+            };
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Parse and verify a program in the text format.
+    pub fn parse(text: &str) -> Result<Program, LoadError> {
+        let mut program = Program {
+            registers: 0,
+            variables: Vec::new(),
+            result: 0,
+            instructions: Vec::new()
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let parse_error = |message: &str| LoadError::Parse { line: i + 1, message: message.to_string() };
+
+            let operands = &words[1..];
+            let registers: Vec<Option<usize>> = operands.iter().map(|w| {
+                if w.starts_with("r") { w[1..].parse().ok() } else { None }
+            }).collect();
+            let register = |n: usize| match registers.get(n) {
+                Some(&Some(r)) => Ok(r),
+                _ => Err(parse_error("expected a register"))
+            };
+            let address = |n: usize| match operands.get(n).and_then(|w| w.parse().ok()) {
+                Some(target) => Ok(target),
+                None => Err(parse_error("expected an address"))
+            };
+            let count = |n: usize| match operands.get(n).and_then(|w| w.parse().ok()) {
+                Some(count) => Ok(count),
+                None => Err(parse_error("expected a number of registers"))
+            };
+            let arity = match words[0] {
+                "registers" | "result" | "jump" => 1,
+                "const" | "move" | "jump-if-false" => 2,
+                "add" | "mul" | "lt" => 3,
+                "variables" => operands.len(),
+                _ => return Err(parse_error("unknown instruction"))
+            };
+            if operands.len() != arity {
+                return Err(parse_error("wrong number of operands"));
+            }
+
+            match words[0] {
+                "registers" => program.registers = try!(count(0)),
+                "variables" => program.variables = operands.iter().map(|w| w.to_string()).collect(),
+                "result" => program.result = try!(register(0)),
+                "const" => {
+                    let val = match operands[1] {
+                        "true" => Element::Boolean(true),
+                        "false" => Element::Boolean(false),
+                        "do-nothing" => Element::DoNothing,
+                        n => match n.parse() {
+                            Ok(n) => Element::Number(n),
+                            Err(_) => return Err(parse_error("expected a constant"))
+                        }
+                    };
+                    program.instructions.push(Instruction::Const(try!(register(0)), val));
+                },
+                "move" => program.instructions.push(Instruction::Move(try!(register(0)), try!(register(1)))),
+                "add" => program.instructions.push(
+                    Instruction::Add(try!(register(0)), try!(register(1)), try!(register(2)))),
+                "mul" => program.instructions.push(
+                    Instruction::Multiply(try!(register(0)), try!(register(1)), try!(register(2)))),
+                "lt" => program.instructions.push(
+                    Instruction::LessThan(try!(register(0)), try!(register(1)), try!(register(2)))),
+                "jump" => program.instructions.push(Instruction::Jump(try!(address(0)))),
+                _ => program.instructions.push(Instruction::JumpIfFalse(try!(register(0)), try!(address(1))))
+            }
+        }
+
+        match program.verify() {
+            Ok(()) => Ok(program),
+            Err(e) => Err(LoadError::Verify(e))
+        }
+    }
+
+    /// Write the program to a file in the text format.
+    pub fn save(&self, path: &Path) -> Result<(), LoadError> {
+        let mut file = try!(File::create(path).map_err(|e| LoadError::Io(e.to_string())));
+        file.write_all(self.to_text().as_bytes()).map_err(|e| LoadError::Io(e.to_string()))
+    }
+}
+
+/// Load and verify a program from a file in the text format.
+pub fn load(path: &Path) -> Result<Program, LoadError> {
+    let mut text = String::new();
+    let mut file = try!(File::open(path).map_err(|e| LoadError::Io(e.to_string())));
+    try!(file.read_to_string(&mut text).map_err(|e| LoadError::Io(e.to_string())));
+    Program::parse(&text)
+}
+
+/// The number of a Number or Boolean, just like `Element::value` does it.
+fn number(val: &Element) -> Option<i64> {
+    match *val {
+        Element::Number(n) => Some(n),
+        Element::Boolean(b) => Some(if b { 1 } else { 0 }),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+fn assert_agrees_with_machine(program: Box<Element>, env: HashMap<String, Box<Element>>) {
+    use {Machine, Outcome};
+
+    let mut m = Machine::new(program.clone(), env.clone());
+    assert_eq!(Outcome::Done, m.run());
+
+    let mut vm_env = env;
+    let result = compile(&program).unwrap().run(&mut vm_env, 10000);
+
+    assert_eq!(Ok(m.expression().clone()), result);
+    assert_eq!(m.clone_env(), vm_env);
+}
+
+#[test]
+fn test_register_machine_agrees_with_machine() {
+    assert_agrees_with_machine(multiply!(add!(number!(3), number!(4)), number!(2)), HashMap::new());
+    assert_agrees_with_machine(ifelse!(less_than!(number!(1), number!(2)), number!(1), number!(2)), HashMap::new());
+
+    let mut env = HashMap::new();
+    env.insert("y".to_string(), number!(1));
+    assert_agrees_with_machine(
+        sequence!(
+            assign!("x", number!(3)),
+            assign!("res", add!(add!(number!(38), variable!("x")), variable!("y")))
+            ),
+        env);
+
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+    assert_agrees_with_machine(
+        while_!(
+            less_than!(variable!("x"), number!(5)),
+            assign!("x", multiply!(variable!("x"), number!(3)))
+            ),
+        env);
+}
+
+#[test]
+fn test_verify_rejects_malformed_programs() {
+    let program = Program {
+        registers: 2,
+        variables: vec!["x".to_string()],
+        result: 1,
+        instructions: vec![
+            Instruction::Const(1, Element::Number(5)),
+            Instruction::JumpIfFalse(1, 3)
+        ]
+    };
+    assert_eq!(Err(VerifyError::JumpOutOfRange { at: 1, target: 3 }), program.verify());
+
+    let mut bad = program.clone();
+    bad.instructions[1] = Instruction::Add(0, 0, 2);
+    assert_eq!(Err(VerifyError::BadRegister { at: 1, register: 2 }), bad.verify());
+
+    let mut bad = program.clone();
+    bad.result = 2;
+    assert_eq!(Err(VerifyError::BadResult(2)), bad.verify());
+
+    let mut bad = program.clone();
+    bad.variables.push("x".to_string());
+    assert_eq!(Err(VerifyError::DuplicateVariable("x".to_string())), bad.verify());
+
+    let mut bad = program.clone();
+    bad.instructions[0] = Instruction::Const(1, Element::Variable("x".to_string()));
+    assert_eq!(Err(VerifyError::BadConstant { at: 0 }), bad.verify());
+
+    assert!(bad.run(&mut HashMap::new(), 10000).is_err());
+}
+
+#[test]
+fn test_parse_and_load() {
+    let program = compile(&while_!(
+        less_than!(variable!("x"), number!(5)),
+        assign!("x", multiply!(variable!("x"), number!(3)))
+        )).unwrap();
+
+    assert_eq!(Ok(program.clone()), Program::parse(&program.to_text()));
+
+    let path = ::std::env::temp_dir().join("small_step_simple_register_test.txt");
+    program.save(&path).unwrap();
+    let loaded = load(&path).unwrap();
+    let _ = ::std::fs::remove_file(&path);
+
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+    assert_eq!(Ok(Element::DoNothing), loaded.run(&mut env, 10000));
+    assert_eq!(Some(&number!(9)), env.get("x"));
+
+    assert_eq!(
+        Err(LoadError::Parse { line: 3, message: "expected a register".to_string() }),
+        Program::parse("registers 1\nresult r0\nconst x 1\n"));
+    assert_eq!(
+        Err(LoadError::Verify(VerifyError::BadRegister { at: 0, register: 7 })),
+        Program::parse("registers 1\nresult r0\nmove r0 r7\n"));
+    assert!(load(Path::new("/does/not/exist")).is_err());
+}
+
+#[test]
+fn test_hostile_programs_are_stopped() {
+    // Rejected before anything is allocated.
+    assert_eq!(
+        Err(LoadError::Verify(VerifyError::TooManyRegisters(1 << 40))),
+        Program::parse(&format!("registers {}\nresult r0\n", 1u64 << 40)));
+    assert_eq!(
+        Err(LoadError::Verify(VerifyError::TooManyRegisters(MAX_REGISTERS + 1))),
+        Program::parse(&format!("registers {}\nresult r0\n", MAX_REGISTERS + 1)));
+    assert!(Program::parse(&format!("registers {}\nresult r0\n", MAX_REGISTERS)).is_ok());
+    assert_eq!(
+        Err(LoadError::Parse { line: 1, message: "expected a number of registers".to_string() }),
+        Program::parse("registers r4\nresult r0\n"));
+
+    let program = Program::parse("registers 1\nresult r0\njump 0\n").unwrap();
+    assert_eq!(Err("gave up after 1000 steps".to_string()), program.run(&mut HashMap::new(), 1000));
+
+    // The example of the module documentation.
+    let program = Program::parse(
+        "registers 4\nvariables x\nresult r3\nconst r1 5\nlt r2 r0 r1\njump-if-false r2 5\n\
+         mul r0 r0 r1\njump 0\nconst r3 do-nothing\n").unwrap();
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(2));
+    assert_eq!(Ok(Element::DoNothing), program.run(&mut env, 100));
+    assert_eq!(Some(&number!(10)), env.get("x"));
+}
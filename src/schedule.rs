@@ -0,0 +1,118 @@
+//! Schedulers deciding nondeterministic choices of a machine.
+//!
+//! Whenever both sides of a `Parallel` element can take a step, or a `Choose` element is
+//! reduced, the machine asks its scheduler which side to take. The decisions are recorded by
+//! the machine, so a run can be replayed exactly with `Machine::replay`.
+
+use Element;
+
+/// Decides between two elements.
+pub trait Scheduler {
+    /// `false` to take the left element, `true` to take the right one.
+    fn schedule(&mut self, left: &Element, right: &Element) -> bool;
+}
+
+/// Any closure can be used as a scheduler.
+impl<F: FnMut(&Element, &Element) -> bool> Scheduler for F {
+    fn schedule(&mut self, left: &Element, right: &Element) -> bool {
+        self(left, right)
+    }
+}
+
+/// Takes the left and the right side in turns.
+pub struct RoundRobin {
+    next: bool
+}
+
+impl RoundRobin {
+    /// Create a new scheduler starting with the left side
+    pub fn new() -> RoundRobin {
+        RoundRobin { next: false }
+    }
+}
+
+impl Scheduler for RoundRobin {
+    fn schedule(&mut self, _: &Element, _: &Element) -> bool {
+        let decision = self.next;
+        self.next = !self.next;
+        decision
+    }
+}
+
+/// Takes a side at random. The same seed always leads to the same decisions.
+pub struct Random {
+    state: u64
+}
+
+impl Random {
+    /// Create a new scheduler from a seed
+    pub fn new(seed: u64) -> Random {
+        // xorshift gets stuck on a zero state.
+        Random { state: if seed == 0 { 0x9E3779B97F4A7C15 } else { seed } }
+    }
+}
+
+impl Scheduler for Random {
+    fn schedule(&mut self, _: &Element, _: &Element) -> bool {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 32) & 1 == 1
+    }
+}
+
+#[test]
+fn test_round_robin_alternates() {
+    let mut s = RoundRobin::new();
+    let decisions: Vec<bool> = (0..4).map(|_| s.schedule(&Element::DoNothing, &Element::DoNothing)).collect();
+    assert_eq!(vec![false, true, false, true], decisions);
+}
+
+#[test]
+fn test_random_is_reproducible() {
+    let mut a = Random::new(42);
+    let mut b = Random::new(42);
+    let decisions_a: Vec<bool> = (0..64).map(|_| a.schedule(&Element::DoNothing, &Element::DoNothing)).collect();
+    let decisions_b: Vec<bool> = (0..64).map(|_| b.schedule(&Element::DoNothing, &Element::DoNothing)).collect();
+
+    assert_eq!(decisions_a, decisions_b);
+    assert!(decisions_a.contains(&true));
+    assert!(decisions_a.contains(&false));
+}
+
+#[test]
+fn test_random_run_can_be_replayed() {
+    use std::collections::hash_map::HashMap;
+    use Machine;
+
+    let program = parallel!(
+        sequence!(assign!("x", number!(1)), print_!(variable!("x"))),
+        sequence!(assign!("x", number!(2)), print_!(variable!("x")))
+        );
+
+    let mut m = Machine::new(program.clone(), HashMap::new());
+    m.set_scheduler(box Random::new(7));
+    m.run();
+
+    let mut replayed = Machine::new(program, HashMap::new());
+    replayed.replay(m.decisions().to_vec());
+    replayed.run();
+
+    assert_eq!(m.output(), replayed.output());
+    assert_eq!(m.clone_env(), replayed.clone_env());
+}
+
+#[test]
+fn test_closure_scheduler() {
+    use std::collections::hash_map::HashMap;
+    use Machine;
+
+    // Always step the right side first.
+    let mut m = Machine::new(
+        parallel!(assign!("x", number!(1)), assign!("x", number!(2))),
+        HashMap::new());
+    m.set_scheduler(box |_: &Element, _: &Element| true);
+    m.run();
+
+    assert_eq!(1, m.clone_env().get(&"x".to_string()).unwrap().value());
+}
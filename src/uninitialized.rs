@@ -0,0 +1,245 @@
+//! Finds reads of variables that may not be set yet.
+//!
+//! Reading a variable that is not set results in `do-nothing` instead of an error, which
+//! usually surfaces much later as a type mismatch somewhere else. `unassigned_reads` follows the
+//! program forward, keeping the set of variables that are definitely set on every path, and
+//! reports every read of a variable outside of it.
+
+use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
+
+use Element;
+
+/// A read of a variable that may not be set.
+#[derive(Debug,PartialEq)]
+pub struct UnassignedRead {
+    pub variable: String,
+    /// The way from the whole program to the reading element, e.g. `["second", "value", "left"]`
+    /// for `x` in `y = 1; z = x + y`. Children are named after the parts of their parent,
+    /// `first`, `condition`, `body` and so on, items of lists by their index and fields of
+    /// records by their name.
+    pub path: Vec<String>
+}
+
+/// Every read of a variable that is not definitely set by then, starting with the variables of
+/// the environment, in the order of the program.
+///
+/// Loops may run zero times, so the variables they set are not set after them. A local variable
+/// is set in the body of its block, and afterwards only if it was before.
+pub fn unassigned_reads(element: &Element, environment: &HashMap<String, Box<Element>>) -> Vec<UnassignedRead> {
+    let mut reads = Vec::new();
+    visit(element, environment.keys().cloned().collect(), &mut Vec::new(), &mut reads);
+    reads
+}
+
+/// Look for reads in an element, given the variables set before it. Returns the variables set
+/// after it.
+fn visit(element: &Element, mut set: HashSet<String>, path: &mut Vec<String>, reads: &mut Vec<UnassignedRead>)
+         -> HashSet<String> {
+    let children: Vec<(&Element, String)> = element.children().into_iter().zip(element.labels().into_iter()).collect();
+
+    match *element {
+        Element::Variable(ref name) => {
+            if !set.contains(name) {
+                reads.push(UnassignedRead { variable: name.clone(), path: path.clone() });
+            }
+            set
+        },
+        Element::Assign(ref name, _) => {
+            let mut set = visit_child(&children[0], set, path, reads);
+            set.insert(name.clone());
+            set
+        },
+        Element::Read(ref name) => {
+            set.insert(name.clone());
+            set
+        },
+        Element::AssignIndex(ref name, _, _) | Element::AssignField(ref name, _, _) => {
+            // The list or record is changed in place, it has to be there.
+            for i in 0..children.len() {
+                set = visit_child(&children[i], set, path, reads);
+            }
+            if !set.contains(name) {
+                reads.push(UnassignedRead { variable: name.clone(), path: path.clone() });
+            }
+            set
+        },
+        Element::IfElse(..) | Element::Choose(..) => {
+            let offset = children.len() - 2;
+            if offset == 1 {
+                set = visit_child(&children[0], set, path, reads);
+            }
+            let cons = visit_child(&children[offset], set.clone(), path, reads);
+            let alt = visit_child(&children[offset + 1], set, path, reads);
+            cons.intersection(&alt).cloned().collect()
+        },
+        Element::Parallel(..) => {
+            // Either side may run first.
+            let l = visit_child(&children[0], set.clone(), path, reads);
+            let r = visit_child(&children[1], set, path, reads);
+            l.union(&r).cloned().collect()
+        },
+        Element::While(_, _, ref invariant) => {
+            if invariant.is_some() {
+                set = visit_child(&children[2], set, path, reads);
+            }
+            let set = visit_child(&children[0], set, path, reads);
+            visit_child(&children[1], set.clone(), path, reads);
+            set
+        },
+        Element::For(..) => {
+            let set = visit_child(&children[0], set, path, reads);
+            let set = visit_child(&children[1], set, path, reads);
+            let after_body = visit_child(&children[3], set.clone(), path, reads);
+            // A `continue` goes on with the update before the rest of the body.
+            let before_update = if children[3].0.may_jump() { set.clone() } else { after_body };
+            visit_child(&children[2], before_update, path, reads);
+            set
+        },
+        Element::DoWhile(..) => {
+            // `break` and `continue` may skip the rest of the body.
+            let after_body = visit_child(&children[0], set.clone(), path, reads);
+            let before_condition = if children[0].0.may_jump() { set } else { after_body };
+            visit_child(&children[1], before_condition, path, reads)
+        },
+        Element::Let(ref name, _, _) => {
+            let mut body = visit_child(&children[0], set.clone(), path, reads);
+            body.insert(name.clone());
+            local(name, &set, visit_child(&children[1], body, path, reads))
+        },
+        Element::Scope(ref name, ref outer, _) => {
+            let mut body = set.clone();
+            body.insert(name.clone());
+            let after = visit_child(&children[children.len() - 1], body, path, reads);
+            let mut before = set;
+            if outer.is_some() {
+                before.insert(name.clone());
+            }
+            local(name, &before, after)
+        },
+        Element::TryCatch(_, ref name, _) => {
+            // The body may throw before doing anything.
+            let body = visit_child(&children[0], set.clone(), path, reads);
+            let mut handler = set.clone();
+            handler.insert(name.clone());
+            let handler = local(name, &set, visit_child(&children[1], handler, path, reads));
+            body.intersection(&handler).cloned().collect()
+        },
+        _ => {
+            for i in 0..children.len() {
+                set = visit_child(&children[i], set, path, reads);
+            }
+            set
+        }
+    }
+}
+
+fn visit_child(&(element, ref label): &(&Element, String), set: HashSet<String>, path: &mut Vec<String>,
+               reads: &mut Vec<UnassignedRead>) -> HashSet<String> {
+    path.push(label.clone());
+    let set = visit(element, set, path, reads);
+    path.pop();
+    set
+}
+
+/// The variables set after a block with a local variable: the outer binding of the local
+/// variable is put back, or removed if there was none.
+fn local(name: &String, before: &HashSet<String>, mut after: HashSet<String>) -> HashSet<String> {
+    if !before.contains(name) {
+        after.remove(name);
+    }
+    after
+}
+
+#[cfg(test)]
+fn read(variable: &str, path: &[&str]) -> UnassignedRead {
+    UnassignedRead { variable: variable.to_string(), path: path.iter().map(|s| s.to_string()).collect() }
+}
+
+#[test]
+fn test_unassigned_reads() {
+    let mut env = HashMap::new();
+    env.insert("y".to_string(), number!(1));
+
+    let program = sequence!(
+        assign!("z", add!(variable!("x"), variable!("y"))),
+        sequence!(assign!("x", number!(1)), assign!("z", add!(variable!("x"), variable!("z"))))
+        );
+    assert_eq!(vec![read("x", &["first", "value", "left"])], unassigned_reads(&program, &env));
+    assert_eq!(
+        vec![read("x", &["first", "value", "left"]), read("y", &["first", "value", "right"])],
+        unassigned_reads(&program, &HashMap::new()));
+}
+
+#[test]
+fn test_unassigned_reads_on_some_paths() {
+    let env = HashMap::new();
+
+    // Only set in one branch.
+    let program = sequence!(
+        ifelse!(boolean!(true), assign!("x", number!(1)), assign!("y", number!(1))),
+        add!(variable!("x"), variable!("y"))
+        );
+    assert_eq!(
+        vec![read("x", &["second", "left"]), read("y", &["second", "right"])],
+        unassigned_reads(&program, &env));
+
+    // Set in both branches.
+    let program = sequence!(
+        ifelse!(boolean!(true), assign!("x", number!(1)), assign!("x", number!(2))),
+        variable!("x")
+        );
+    assert!(unassigned_reads(&program, &env).is_empty());
+
+    // A loop body may not run at all.
+    let program = sequence!(
+        while_!(boolean!(false), assign!("x", number!(1))),
+        variable!("x")
+        );
+    assert_eq!(vec![read("x", &["second"])], unassigned_reads(&program, &env));
+
+    // `continue` skips setting `x` before the update or condition.
+    let program = for_!(
+        assign!("i", number!(0)),
+        less_than!(variable!("i"), number!(3)),
+        assign!("x", add!(variable!("x"), number!(1))),
+        sequence!(if_!(variable!("c"), continue_!()), assign!("x", number!(0)))
+        );
+    let mut env = HashMap::new();
+    env.insert("c".to_string(), boolean!(true));
+    assert_eq!(vec![read("x", &["update", "value", "left"])], unassigned_reads(&program, &env));
+
+    let program = do_while!(
+        sequence!(if_!(variable!("c"), continue_!()), assign!("x", number!(0))),
+        less_than!(variable!("x"), number!(3))
+        );
+    assert_eq!(vec![read("x", &["condition", "left"])], unassigned_reads(&program, &env));
+
+    // Without them the body is done before the condition.
+    let program = do_while!(assign!("x", number!(0)), less_than!(variable!("x"), number!(3)));
+    assert!(unassigned_reads(&program, &env).is_empty());
+}
+
+#[test]
+fn test_unassigned_reads_of_locals() {
+    let env = HashMap::new();
+
+    let program = sequence!(
+        let_!("x", number!(1), print_!(variable!("x"))),
+        sequence!(
+            try_catch!(throw!(number!(1)), "e", print_!(variable!("e"))),
+            append!(variable!("x"), variable!("e"))
+            )
+        );
+    assert_eq!(
+        vec![read("x", &["second", "second", "list"]), read("e", &["second", "second", "item"])],
+        unassigned_reads(&program, &env));
+
+    let program = sequence!(
+        assign_index!("l", number!(0), number!(1)),
+        sequence!(assign!("l", list!(number!(1))), assign_index!("l", number!(0), variable!("v")))
+        );
+    assert_eq!(
+        vec![read("l", &["first"]), read("v", &["second", "second", "value"])],
+        unassigned_reads(&program, &env));
+}
@@ -0,0 +1,334 @@
+//! A machine that keeps its place in the expression between steps.
+//!
+//! `Machine` reduces the whole expression on every step, so `reduce` walks down from the top to
+//! the part that actually changes each time. This machine keeps the expression taken apart
+//! instead: the part being reduced is in focus, and a stack of frames holds everything around
+//! it, innermost last. A step walks down from the focus to the next part to reduce, reduces it
+//! and only walks up as far as needed. The whole expression is put back together on demand.
+//!
+//! Each step yields exactly the expression `Machine` would have after the same step.
+
+use std::collections::hash_map::HashMap;
+use std::mem;
+
+use {Context, Element, Outcome};
+
+/// An element with one of its parts taken out.
+struct Frame {
+    parent: Element,
+    child: usize
+}
+
+/// The part of `element` with the given index. Parts are counted in the order of the boxed
+/// fields of each element, the items of lists and the fields of records.
+fn child_mut(element: &mut Element, index: usize) -> &mut Box<Element> {
+    match (element, index) {
+        (&mut Element::Add(ref mut l, _), 0) => l,
+        (&mut Element::Add(_, ref mut r), 1) => r,
+        (&mut Element::Multiply(ref mut l, _), 0) => l,
+        (&mut Element::Multiply(_, ref mut r), 1) => r,
+        (&mut Element::LessThan(ref mut l, _), 0) => l,
+        (&mut Element::LessThan(_, ref mut r), 1) => r,
+        (&mut Element::Assign(_, ref mut val), 0) => val,
+        (&mut Element::Sequence(ref mut first, _), 0) => first,
+        (&mut Element::IfElse(ref mut cond, _, _), 0) => cond,
+        (&mut Element::For(ref mut init, _, _, _), 0) => init,
+        (&mut Element::Iteration(ref mut body, _), 0) => body,
+        (&mut Element::Let(_, ref mut init, _), 0) => init,
+        (&mut Element::Scope(_, _, ref mut body), 0) => body,
+        (&mut Element::List(ref mut items), i) => &mut items[i],
+        (&mut Element::Index(ref mut list, _), 0) => list,
+        (&mut Element::Index(_, ref mut index), 1) => index,
+        (&mut Element::AssignIndex(_, ref mut index, _), 0) => index,
+        (&mut Element::AssignIndex(_, _, ref mut val), 1) => val,
+        (&mut Element::Length(ref mut list), 0) => list,
+        (&mut Element::Append(ref mut list, _), 0) => list,
+        (&mut Element::Append(_, ref mut item), 1) => item,
+        (&mut Element::Record(ref mut fields), i) => &mut fields[i].1,
+        (&mut Element::Field(ref mut record, _), 0) => record,
+        (&mut Element::AssignField(_, _, ref mut val), 0) => val,
+        (&mut Element::Print(ref mut val), 0) => val,
+        (&mut Element::Assert(ref mut cond, _), 0) => cond,
+        (&mut Element::Throw(ref mut val), 0) => val,
+        (&mut Element::TryCatch(ref mut body, _, _), 0) => body,
+        (element, index) => panic!("no part {} in {:?}", index, element)
+    }
+}
+
+/// The index of the part `reduce` reduces next when reducing `element`, if reducing the element
+/// means nothing but reducing that part. `None` if the element itself has to be reduced.
+fn next_child(element: &Element) -> Option<usize> {
+    // A thrown value, `break` or `continue` is handled by the element around it.
+    match element.operands().into_iter().find(|o| o.is_reducible() || o.is_abrupt()) {
+        Some(abrupt) if abrupt.is_abrupt() => return None,
+        _ => {}
+    }
+
+    let first_reducible = |parts: &[&Element]| parts.iter().position(|p| p.is_reducible());
+
+    match *element {
+        Element::Add(ref l, ref r) |
+        Element::Multiply(ref l, ref r) |
+        Element::LessThan(ref l, ref r) |
+        Element::Index(ref l, ref r) |
+        Element::AssignIndex(_, ref l, ref r) |
+        Element::Append(ref l, ref r) => first_reducible(&[l, r]),
+        Element::Assign(_, ref part) |
+        Element::IfElse(ref part, _, _) |
+        Element::Iteration(ref part, _) |
+        Element::Let(_, ref part, _) |
+        Element::Scope(_, _, ref part) |
+        Element::Length(ref part) |
+        Element::Field(ref part, _) |
+        Element::AssignField(_, _, ref part) |
+        Element::Print(ref part) |
+        Element::Assert(ref part, _) |
+        Element::Throw(ref part) |
+        Element::TryCatch(ref part, _, _) => first_reducible(&[part]),
+        Element::Sequence(box Element::DoNothing, _) => None,
+        Element::Sequence(ref first, _) => first_reducible(&[first]),
+        Element::For(box Element::DoNothing, _, _, _) => None,
+        Element::For(ref init, _, _, _) => first_reducible(&[init]),
+        Element::List(ref items) => items.iter().position(|i| i.is_reducible()),
+        Element::Record(ref fields) => fields.iter().position(|&(_, ref v)| v.is_reducible()),
+        _ => None
+    }
+}
+
+/// A virtual machine like `Machine`, keeping its place in the expression between steps.
+pub struct ZipperMachine {
+    focus: Element,
+    frames: Vec<Frame>,
+    environment: HashMap<String, Box<Element>>,
+    context: Context
+}
+
+impl ZipperMachine {
+    /// Create a new machine with a given expression and an environment
+    pub fn new(expression: Box<Element>, map: HashMap<String, Box<Element>>) -> ZipperMachine {
+        ZipperMachine {
+            focus: *expression,
+            frames: Vec::new(),
+            environment: map,
+            context: Context::new()
+        }
+    }
+
+    /// The whole current expression, put back together
+    pub fn expression(&self) -> Element {
+        let mut expression = self.focus.clone();
+        for frame in self.frames.iter().rev() {
+            let mut parent = frame.parent.clone();
+            *child_mut(&mut parent, frame.child) = box expression;
+            expression = parent;
+        }
+        expression
+    }
+
+    /// As the environment is passed in immutable, we need to clone it to get it back
+    pub fn clone_env(&self) -> HashMap<String, Box<Element>> {
+        self.environment.clone()
+    }
+
+    /// Everything the program printed so far
+    pub fn output(&self) -> &[Box<Element>] {
+        &self.context.output
+    }
+
+    /// Append values to the input of the program
+    pub fn add_input(&mut self, input: Vec<Box<Element>>) {
+        self.context.input.extend(input.into_iter());
+    }
+
+    /// Wether or not the whole expression is reducible
+    pub fn is_reducible(&self) -> bool {
+        // The focus only stays below the top while there is something left to reduce in it.
+        self.focus.is_reducible()
+    }
+
+    /// The number of elements around the focus
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Reduce one step of our current expression
+    pub fn step(&mut self) {
+        self.context.waiting_for_input = false;
+        self.context.failed_assertion = None;
+
+        while let Some(index) = next_child(&self.focus) {
+            let child = mem::replace(child_mut(&mut self.focus, index), box Element::DoNothing);
+            let parent = mem::replace(&mut self.focus, *child);
+            self.frames.push(Frame { parent: parent, child: index });
+        }
+
+        let next = self.focus.reduce_in(&mut self.environment, &mut self.context);
+        // A failed assertion keeps the machine at the assertion.
+        if self.context.failed_assertion.is_none() {
+            self.focus = next;
+        }
+
+        while !self.focus.is_reducible() {
+            match self.frames.pop() {
+                Some(Frame { mut parent, child }) => {
+                    let reduced = mem::replace(&mut self.focus, Element::DoNothing);
+                    *child_mut(&mut parent, child) = box reduced;
+                    self.focus = parent;
+                },
+                None => break
+            }
+        }
+    }
+
+    /// Reduce until we reached a non-reducible expression.
+    /// Unlike `Machine::run` this does not print every step, that would mean putting the whole
+    /// expression back together every time.
+    pub fn run(&mut self) -> Outcome {
+        while self.is_reducible() {
+            self.step();
+            if self.context.waiting_for_input {
+                return Outcome::InputExhausted;
+            }
+            match self.context.failed_assertion {
+                Some(ref message) => {
+                    return Outcome::AssertionFailed {
+                        message: message.clone(),
+                        expression: box self.expression(),
+                        environment: self.environment.clone()
+                    };
+                },
+                None => {}
+            }
+        }
+
+        ::outcome(&self.focus)
+    }
+}
+
+/// Steps both machines side by side, checking they agree on every expression.
+#[cfg(test)]
+fn assert_same_steps(program: Box<Element>, env: HashMap<String, Box<Element>>) -> ZipperMachine {
+    use Machine;
+
+    let mut m = Machine::new(program.clone(), env.clone());
+    let mut z = ZipperMachine::new(program, env);
+
+    loop {
+        assert_eq!(*m.expression(), z.expression());
+        assert_eq!(m.expression().is_reducible(), z.is_reducible());
+        if !z.is_reducible() {
+            break;
+        }
+        m.step();
+        z.step();
+    }
+
+    assert_eq!(m.clone_env(), z.clone_env());
+    assert_eq!(m.output(), z.output());
+    z
+}
+
+#[test]
+fn test_zipper_steps_like_machine() {
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(1));
+    env.insert("a".to_string(), list!(number!(5), number!(6)));
+
+    //  for (i = 0; i < 2; i = i + 1) [ a[i] = a[i] * x; print a ];
+    //  while (x < 5) [ x = x * 3 ];
+    //  p = { x: x, y: length(append(a, 1)) }; p.y = p.x + p.y
+    let program = sequence!(
+        for_!(
+            assign!("i", number!(0)),
+            less_than!(variable!("i"), number!(2)),
+            assign!("i", add!(variable!("i"), number!(1))),
+            sequence!(
+                assign_index!("a", variable!("i"), multiply!(index!(variable!("a"), variable!("i")), variable!("x"))),
+                print_!(variable!("a"))
+                )
+            ),
+        sequence!(
+            while_!(
+                less_than!(variable!("x"), number!(5)),
+                assign!("x", multiply!(variable!("x"), number!(3)))
+                ),
+            sequence!(
+                assign!("p", record!("x" => variable!("x"), "y" => length!(append!(variable!("a"), number!(1))))),
+                assign_field!("p", "y", add!(field!(variable!("p"), "x"), field!(variable!("p"), "y")))
+                )
+            )
+        );
+
+    let z = assert_same_steps(program, env);
+
+    assert_eq!("{ x: 9, y: 12 }".to_string(), format!("{:?}", z.clone_env().get("p").unwrap()));
+}
+
+#[test]
+fn test_zipper_handles_abrupt_completion() {
+    //  x = 0; try [ while (true) [ let i = x in [ if (i < 3) [ x = x + 1; continue ] else [ break ] ] ]; throw x ] catch (e) [ y = e ]
+    let program = sequence!(
+        assign!("x", number!(0)),
+        try_catch!(
+            sequence!(
+                while_!(
+                    boolean!(true),
+                    let_!("i", variable!("x"),
+                        ifelse!(
+                            less_than!(variable!("i"), number!(3)),
+                            sequence!(assign!("x", add!(variable!("x"), number!(1))), continue_!()),
+                            break_!()
+                            )
+                        )
+                    ),
+                throw!(variable!("x"))
+                ),
+            "e",
+            assign!("y", variable!("e"))
+            )
+        );
+
+    let z = assert_same_steps(program, HashMap::new());
+
+    assert_eq!(3, z.clone_env().get("y").unwrap().value());
+}
+
+#[test]
+fn test_zipper_keeps_its_place() {
+    let program = add!(number!(1), add!(number!(2), add!(number!(3), add!(number!(4), number!(5)))));
+    let mut z = ZipperMachine::new(program, HashMap::new());
+
+    z.step();
+    assert_eq!("1 + 2 + 3 + 9".to_string(), format!("{:?}", z.expression()));
+    assert_eq!(2, z.depth());
+
+    z.step();
+    assert_eq!(1, z.depth());
+
+    assert_eq!(Outcome::Done, z.run());
+    assert_eq!(Element::Number(15), z.expression());
+    assert_eq!(0, z.depth());
+}
+
+#[test]
+fn test_zipper_reports_outcomes() {
+    let mut z = ZipperMachine::new(
+        sequence!(read!("x"), assert_!(less_than!(variable!("x"), number!(3)), "x is small")),
+        HashMap::new());
+
+    assert_eq!(Outcome::InputExhausted, z.run());
+
+    z.add_input(vec![number!(5)]);
+    let mut env = HashMap::new();
+    env.insert("x".to_string(), number!(5));
+    assert_eq!(
+        Outcome::AssertionFailed {
+            message: "x is small".to_string(),
+            expression: assert_!(boolean!(false), "x is small"),
+            environment: env
+        },
+        z.run());
+
+    let mut z = ZipperMachine::new(add!(number!(1), index!(list!(), number!(0))), HashMap::new());
+    assert_eq!(Outcome::Error("index 0 out of bounds for list of length 0".to_string()), z.run());
+}